@@ -0,0 +1,272 @@
+//! per-key access control of encrypt mode, which ops and children each key can use
+use super::client::{Command, Ops};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::io::{Error as ioError, ErrorKind, Result};
+use yaml_rust::Yaml;
+
+/// permissions of one key
+///
+/// ```yaml
+/// key_acl:
+///   alice:
+///     ops: [read, control]
+///     children: ["web-*", worker]
+///   deploy-bot:
+///     ops: [read, restart]
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyAcl {
+    /// groups ("read", "control", "kill") or op names
+    pub ops: Vec<String>,
+
+    /// child name patterns, "*" matches any characters. None is all children
+    pub children: Option<Vec<String>>,
+}
+
+impl KeyAcl {
+    pub fn new(input: &Yaml) -> Result<Self> {
+        let list = |name: &str| -> Result<Option<Vec<String>>> {
+            match &input[name] {
+                Yaml::BadValue => Ok(None),
+                Yaml::Array(v) => v
+                    .iter()
+                    .map(|x| {
+                        x.as_str().map(|s| s.to_string()).ok_or_else(|| {
+                            ioError::new(
+                                ErrorKind::InvalidData,
+                                format!("{} of key_acl should be strings", name),
+                            )
+                        })
+                    })
+                    .collect::<Result<Vec<String>>>()
+                    .map(Some),
+                _ => Err(ioError::new(
+                    ErrorKind::InvalidData,
+                    format!("{} of key_acl should be a list", name),
+                )),
+            }
+        };
+
+        let ops = list("ops")?.unwrap_or_default();
+        for op in &ops {
+            if !GROUPS.contains(&op.as_str()) && Ops::from_str(op).is_err() {
+                return Err(ioError::new(
+                    ErrorKind::InvalidData,
+                    format!("unknown op {} in key_acl", op),
+                ));
+            }
+        }
+
+        Ok(KeyAcl {
+            ops,
+            children: list("children")?,
+        })
+    }
+
+    /// command is allowed by ops and children of this key
+    pub fn allows(&self, command: &Command) -> bool {
+        let op = command.get_ops();
+        let op_allowed = self
+            .ops
+            .iter()
+            .any(|o| o == group(&op) || *o == op.to_string());
+
+        // read ops without child, like status, only show children of scope.
+        // control ops without child mean all children, like reopen
+        let child_allowed = match (&self.children, &command.child_name) {
+            (Some(patterns), Some(name)) => patterns.iter().any(|p| pattern_match(p, name)),
+            (Some(_), None) => group(&op) != "control",
+            _ => true,
+        };
+
+        op_allowed && child_allowed
+    }
+}
+
+/// key_acl field of server config. When it is given, keys not listed can do nothing
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Acl(HashMap<String, KeyAcl>);
+
+impl Acl {
+    pub fn new(input: &Yaml) -> Result<Self> {
+        let keys = input.as_hash().ok_or_else(|| {
+            ioError::new(
+                ErrorKind::InvalidData,
+                "key_acl should be a map of key names",
+            )
+        })?;
+
+        let mut acl = HashMap::new();
+        for (name, v) in keys {
+            let name = name.as_str().ok_or_else(|| {
+                ioError::new(
+                    ErrorKind::InvalidData,
+                    "key name of key_acl should be string",
+                )
+            })?;
+            acl.insert(name.to_string(), KeyAcl::new(v)?);
+        }
+        Ok(Acl(acl))
+    }
+
+    /// children key can see in output of ops without child name
+    pub fn scope(&self, key: &str) -> ChildScope {
+        ChildScope(self.0.get(key).and_then(|k| k.children.clone()))
+    }
+
+    /// error is AccessDenied if key cannot run command
+    pub fn check(&self, key: &str, command: &Command) -> Result<()> {
+        match self.0.get(key) {
+            Some(k) if k.allows(command) => Ok(()),
+            _ => Err(ioError::new(
+                ErrorKind::PermissionDenied,
+                AccessDenied(format!(
+                    "key {} is not allowed to {}{}",
+                    key,
+                    command.get_ops().to_string(),
+                    command
+                        .child_name
+                        .as_ref()
+                        .map(|c| format!(" {}", c))
+                        .unwrap_or_default()
+                )),
+            )),
+        }
+    }
+}
+
+/// children one command can see, by children patterns of its key
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ChildScope(Option<Vec<String>>);
+
+impl ChildScope {
+    /// every child, for keys without children limit and commands of server itself
+    pub fn all() -> Self {
+        ChildScope(None)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.0
+            .as_ref()
+            .is_none_or(|patterns| patterns.iter().any(|p| pattern_match(p, name)))
+    }
+}
+
+/// command refused by key_acl, different from key or signature is wrong
+#[derive(Debug)]
+pub struct AccessDenied(String);
+
+impl fmt::Display for AccessDenied {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "access denied: {}", self.0)
+    }
+}
+
+impl Error for AccessDenied {}
+
+/// error is made by Acl::check
+pub fn is_denied(e: &ioError) -> bool {
+    e.get_ref().is_some_and(|inner| inner.is::<AccessDenied>())
+}
+
+const GROUPS: [&str; 3] = ["read", "control", "kill"];
+
+/// group of op: "read" only looks (keys too), "control" changes children, "kill" stops server.
+/// every op is listed, so new op has to choose its group
+fn group(op: &Ops) -> &'static str {
+    match op {
+        Ops::Start | Ops::Restart | Ops::Stop | Ops::TryStart | Ops::Reopen => "control",
+        Ops::Kill => "kill",
+        Ops::Help
+        | Ops::Info
+        | Ops::Check
+        | Ops::Logs
+        | Ops::History
+        | Ops::Status
+        | Ops::List
+        | Ops::Diff
+        | Ops::Keys => "read",
+    }
+}
+
+/// "*" matches any characters, others match themselves
+fn pattern_match(pattern: &str, name: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == name,
+        Some((head, rest)) => match name.strip_prefix(head) {
+            Some(name) => (0..=name.len())
+                .filter(|i| name.is_char_boundary(*i))
+                .any(|i| pattern_match(rest, &name[i..])),
+            None => false,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use yaml_rust::YamlLoader;
+
+    fn command(s: &str) -> Command {
+        Command::new_from_str(s.split(' ').collect()).unwrap()
+    }
+
+    #[test]
+    fn test_key_acl() {
+        let docs = YamlLoader::load_from_str(
+            "key_acl:\n  alice:\n    ops: [read, control]\n    children: [\"web-*\", worker]\n  bot:\n    ops: [read, restart]",
+        )
+        .unwrap();
+        let acl = Acl::new(&docs[0]["key_acl"]).unwrap();
+
+        assert!(acl.check("alice", &command("restart web-01")).is_ok());
+        assert!(acl.check("alice", &command("stop worker")).is_ok());
+        assert!(acl.check("alice", &command("status")).is_ok());
+        assert!(acl.check("alice", &command("stop db")).is_err());
+        assert!(acl.check("alice", &command("kill")).is_err());
+
+        // ops without child: read ones see scope only, control ones mean all children
+        assert!(acl.check("alice", &command("check")).is_ok());
+        assert!(acl.check("alice", &command("reopen")).is_err());
+        assert!(acl.check("alice", &command("reopen all")).is_err());
+        let scope = acl.scope("alice");
+        assert!(scope.contains("web-01") && scope.contains("worker"));
+        assert!(!scope.contains("db"));
+        assert!(acl.scope("bot").contains("db"));
+        assert!(ChildScope::all().contains("db"));
+
+        assert!(acl.check("bot", &command("restart db")).is_ok());
+        assert!(acl.check("bot", &command("logs db")).is_ok());
+        assert!(acl.check("bot", &command("stop db")).is_err());
+
+        // keys not listed
+        let e = acl.check("eve", &command("status")).unwrap_err();
+        assert!(is_denied(&e));
+        assert_eq!(
+            e.to_string(),
+            "access denied: key eve is not allowed to status"
+        );
+        assert!(!is_denied(&ioError::new(
+            ErrorKind::PermissionDenied,
+            "bad key"
+        )));
+
+        let docs = YamlLoader::load_from_str("a:\n  ops: [fly]\nb:\n  ops: read").unwrap();
+        assert!(KeyAcl::new(&docs[0]["a"]).is_err());
+        assert!(KeyAcl::new(&docs[0]["b"]).is_err());
+    }
+
+    #[test]
+    fn test_pattern_match() {
+        assert!(pattern_match("web-*", "web-01"));
+        assert!(pattern_match("web-*", "web-"));
+        assert!(pattern_match("*-worker", "mail-worker"));
+        assert!(pattern_match("a*b*c", "a-b-b-c"));
+        assert!(pattern_match("*", "anything"));
+        assert!(!pattern_match("web-*", "db-01"));
+        assert!(!pattern_match("worker", "worker2"));
+        assert!(!pattern_match("a*b", "a-b-c"));
+    }
+}
@@ -0,0 +1,175 @@
+//! audit log of every client command, one json object per line.
+//! Rejected requests (bad key, cannot decrypt) and ones denied by key_acl are recorded too.
+use super::acl;
+use super::child::child_log::LogFile;
+use super::child::child_output::Output;
+use super::client::{Command, Flags};
+use chrono::prelude::*;
+use serde_json::{Map, Value};
+use std::io::{Result, Write};
+use std::sync::Mutex;
+
+static AUDIT: Mutex<Option<LogFile>> = Mutex::new(None);
+
+/// One client request
+#[derive(Debug, Default)]
+pub struct Record {
+    /// peer address of client
+    pub client: String,
+
+    /// key name used in encrypt mode
+    pub key: Option<String>,
+
+    /// decoded command, None if request is rejected before decoding
+    pub command: Option<String>,
+
+    /// transport server sees, "tcp", "tls", "unix", "http", or "internal" of posthook
+    pub transport: &'static str,
+}
+
+impl Record {
+    pub fn new(client: &str) -> Self {
+        Record {
+            client: client.to_string(),
+            transport: "tcp",
+            ..Default::default()
+        }
+    }
+
+    fn to_line(&self, result: &Result<String>) -> String {
+        let command = self
+            .command
+            .as_ref()
+            .and_then(|c| Command::new_from_str(c.split(' ').collect()).ok());
+
+        let mut obj = Map::new();
+        obj.insert(
+            "time".to_string(),
+            Value::from(Local::now().to_rfc3339_opts(SecondsFormat::Secs, false)),
+        );
+        obj.insert("client".to_string(), Value::from(self.client.as_str()));
+        obj.insert("transport".to_string(), Value::from(self.transport));
+        // clients running inside ssh session mark their command with "--ssh",
+        // server cannot check it, so it is only what client says
+        if command.as_ref().is_some_and(|c| c.has_flag(&Flags::Ssh)) {
+            obj.insert("client_transport".to_string(), Value::from("ssh"));
+        }
+        obj.insert(
+            "key".to_string(),
+            self.key.as_deref().map(Value::from).unwrap_or(Value::Null),
+        );
+        obj.insert(
+            "command".to_string(),
+            self.command
+                .as_deref()
+                .map(Value::from)
+                .unwrap_or(Value::Null),
+        );
+        if let Some(c) = &command {
+            obj.insert("op".to_string(), Value::from(c.get_ops().to_string()));
+            obj.insert(
+                "child".to_string(),
+                c.child_name
+                    .as_deref()
+                    .map(Value::from)
+                    .unwrap_or(Value::Null),
+            );
+        }
+
+        let outcome = match result {
+            _ if self.command.is_none() => "rejected",
+            Ok(_) => "ok",
+            Err(e) if acl::is_denied(e) => "denied",
+            Err(_) => "error",
+        };
+        obj.insert("outcome".to_string(), Value::from(outcome));
+        obj.insert("response".to_string(), Value::from(result_message(result)));
+
+        Value::Object(obj).to_string()
+    }
+}
+
+fn result_message(result: &Result<String>) -> String {
+    match result {
+        Ok(s) => s.trim().to_string(),
+        Err(e) => e.to_string().trim().to_string(),
+    }
+}
+
+/// open audit log file, audit is off if it is never called
+pub fn init(output: &Output) -> Result<()> {
+    let file = LogFile::open(output)?;
+    *AUDIT.lock().unwrap() = Some(file);
+    Ok(())
+}
+
+/// write record and its outcome to audit log
+pub fn write(record: &Record, result: &Result<String>) -> Result<()> {
+    let mut audit = AUDIT.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(file) = audit.as_mut() {
+        let mut line = record.to_line(result);
+        line.push('\n');
+        file.write_all(line.as_bytes())?;
+        file.flush()?;
+    }
+    Ok(())
+}
+
+/// reopen audit log file after external logrotate moved it, return its path
+pub fn reopen() -> Result<Option<String>> {
+    match AUDIT.lock().unwrap_or_else(|e| e.into_inner()).as_mut() {
+        Some(file) => {
+            file.reopen()?;
+            Ok(Some(file.path().to_string()))
+        }
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Error as ioError, ErrorKind};
+
+    #[test]
+    fn test_record_line() {
+        let mut record = Record::new("127.0.0.1:5000");
+        let rejected: Value = serde_json::from_str(
+            &record.to_line(&Err(ioError::new(ErrorKind::NotFound, "Cannot find key"))),
+        )
+        .unwrap();
+        assert_eq!(rejected["outcome"], "rejected");
+        assert_eq!(rejected["transport"], "tcp");
+        assert_eq!(rejected["client_transport"], Value::Null);
+        assert_eq!(rejected["command"], Value::Null);
+        assert_eq!(rejected["response"], "Cannot find key");
+
+        record.key = Some("alice".to_string());
+        record.command = Some("restart child1 --ssh".to_string());
+        let ok: Value =
+            serde_json::from_str(&record.to_line(&Ok("restart child1 success\n".to_string())))
+                .unwrap();
+        assert_eq!(ok["outcome"], "ok");
+        assert_eq!(ok["transport"], "tcp");
+        assert_eq!(ok["client_transport"], "ssh");
+        assert_eq!(ok["key"], "alice");
+        assert_eq!(ok["op"], "restart");
+        assert_eq!(ok["child"], "child1");
+        assert_eq!(ok["response"], "restart child1 success");
+
+        let acl = acl::Acl::new(
+            &yaml_rust::YamlLoader::load_from_str("alice:\n  ops: [read]").unwrap()[0],
+        )
+        .unwrap();
+        let command = Command::new_from_str(vec!["restart", "child1"]).unwrap();
+        let denied: Value = serde_json::from_str(
+            &record.to_line(&acl.check("alice", &command).map(|_| String::new())),
+        )
+        .unwrap();
+        assert_eq!(denied["outcome"], "denied");
+        assert_eq!(
+            denied["response"],
+            "access denied: key alice is not allowed to restart child1"
+        );
+    }
+}
@@ -1,12 +1,15 @@
+use serde_json::{json, Map, Value};
 use std::env;
-use std::net::IpAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::str::FromStr;
+use std::thread;
 use supervisor_rs::client::*;
+use supervisor_rs::protocol;
 
 fn main() {
     let arguments = env::args();
     let change_2_vec = arguments.collect::<Vec<String>>();
-    let cache_command = match Command::new_from_string(change_2_vec[1..].to_vec()) {
+    let mut cache_command = match Command::new_from_string(change_2_vec[1..].to_vec()) {
         Ok(c) => c,
         Err(e) => {
             println!("error: {}", e);
@@ -19,6 +22,12 @@ fn main() {
         return;
     }
 
+    let tls = cache_command.tls_options();
+    let connect = |addr: IpFields<'_>| match &tls {
+        Some(opts) => ConnectionStream::new_tls(addr, opts),
+        None => ConnectionStream::new(addr),
+    };
+
     // build streams, parse all host
     let mut streams: Vec<ConnectionStream> = {
         if let Some(pairs) = cache_command.prep_obj_pairs() {
@@ -28,12 +37,15 @@ fn main() {
             // ip address format can be "127.0.0.1" or "127.0.0.1, 127.0.0.2"
             // or "ssh://username@ipaddress"
             // or "ssh://username@ipaddress ,ssh://username1@ipaddress1"
-            match ip_fields_parser(ip_pair) {
+            // hostnames are resolved here, first address or all of them
+            match ip_fields_parser(ip_pair)
+                .and_then(|addrs| resolve_hosts(addrs, cache_command.has_flag(&Flags::AllAddrs)))
+            {
                 Ok(addrs) => {
                     let mut a = vec![];
                     //creat socket
                     for addr in addrs {
-                        match ConnectionStream::new(addr) {
+                        match connect(addr) {
                             Ok(s) => a.push(s),
                             Err(e) => {
                                 println!("{}", e.to_string());
@@ -54,10 +66,16 @@ fn main() {
     };
 
     if streams.len() == 0 {
+        // local server reached by ssh tunnel, let server audit know it
+        if env::var("SSH_CONNECTION").is_ok() && !cache_command.has_flag(&Flags::Ssh) {
+            cache_command.flags.push(Flags::Ssh);
+        }
+
         // If don't have prep, give local address (ipv4)
-        streams = vec![match ConnectionStream::new(IpFields::Normal(
+        streams = vec![match connect(IpFields::Normal(SocketAddr::new(
             IpAddr::from_str("127.0.0.1").unwrap(),
-        )) {
+            DEFAULT_PORT,
+        ))) {
             Ok(s) => s,
             Err(e) => {
                 println!("{}", e.to_string());
@@ -66,23 +84,96 @@ fn main() {
         }];
     }
 
-    // Here to check/make encrypt data
-    let data_2_server = if let Ok(d) = cache_command.generate_encrypt_wapper() {
-        d.encrypt_to_bytes().unwrap()
-    } else {
-        cache_command.as_bytes()
-    };
+    // Here to check/make signed data, signature is only valid for its server
+    let wrapper = cache_command.generate_encrypt_wapper().ok();
+    let mut requests = vec![];
+    for stream in streams {
+        let addr = stream.address().unwrap();
+        // response is encrypted to reply key of signed command
+        let (data, reply) = match &wrapper {
+            Some(d) => match d.sign_to_bytes(&addr) {
+                Ok((data, reply)) => (data, Some(reply)),
+                Err(e) => {
+                    println!("cannot sign command: {}", e);
+                    return;
+                }
+            },
+            None => (cache_command.as_bytes(), None),
+        };
+        requests.push((stream, addr, data, reply));
+    }
+
+    // keep following output of all servers, each line tagged with its host
+    if cache_command.get_ops() == Ops::Logs && cache_command.has_flag(&Flags::Follow) {
+        let handles = requests
+            .into_iter()
+            .map(|(mut stream, addr, data, reply)| {
+                thread::spawn(move || {
+                    if let Err(e) =
+                        stream.follow_comm(&data, reply, |line| println!("[{}] {}", addr, line))
+                    {
+                        println!("[{}] {}", addr, e);
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for h in handles {
+            let _ = h.join();
+        }
+        return;
+    }
+
+    // one json object per line, tagged with its server
+    if cache_command.output_format() == OutputFormat::Json {
+        for (mut stream, addr, data, reply) in requests {
+            match stream.send_comm(&data, reply) {
+                Ok(resp) => println!("{}", json_with_server(&addr, &resp)),
+                Err(e) => {
+                    let kind = if protocol::is_unauthenticated(&e) {
+                        "unauthenticated"
+                    } else {
+                        "connection_failed"
+                    };
+                    let error = json!({"kind": kind, "message": e.to_string()});
+                    println!(
+                        "{}",
+                        json!({"status": "error", "error": error, "server": addr})
+                    );
+                }
+            }
+        }
+        return;
+    }
 
     //send same commands to all servers
-    for mut stream in streams {
-        print!(
-            "Server {} response:\n{}",
-            stream.address().unwrap(),
-            stream.send_comm(&data_2_server).unwrap()
-        );
+    for (mut stream, addr, data, reply) in requests {
+        match stream.send_comm(&data, reply) {
+            Ok(resp) => print!("Server {} response:\n{}", addr, resp),
+            // like client certificate rejected by server
+            Err(e) => println!("Server {} error: {}", addr, e),
+        }
     }
 }
 
+/// add server address to json response, old server answers text
+fn json_with_server(addr: &str, resp: &str) -> Value {
+    let mut obj = match serde_json::from_str::<Value>(resp) {
+        Ok(Value::Object(o)) => o,
+        _ => {
+            let mut o = Map::new();
+            o.insert("status".to_string(), Value::from("error"));
+            o.insert(
+                "error".to_string(),
+                json!({"kind": "invalid_response", "message": resp.trim()}),
+            );
+            o
+        }
+    };
+    obj.insert("server".to_string(), Value::from(addr));
+    Value::Object(obj)
+}
+
 fn help() -> String {
     String::from(
         "\
@@ -97,9 +188,35 @@ supervisor-rs-client start child1
 
 supervisor-rs-client restart child1 on 192.168.1.1
 
+supervisor-rs-client logs child1 --lines 200 --stderr on 192.168.1.1
+
+supervisor-rs-client logs child1 --follow on 192.168.1.1 on 192.168.1.2
+
+supervisor-rs-client history child1
+
+supervisor-rs-client status
+
+supervisor-rs-client list on 192.168.1.1
+
+supervisor-rs-client diff child1
+
+supervisor-rs-client status --output json on 192.168.1.1
+
+supervisor-rs-client status on 192.168.1.1:33890
+
+supervisor-rs-client status --tls --ca ca.crt --cert me.crt --key me.key on 192.168.1.1
+
+supervisor-rs-client status --all-addrs on web.internal, ssh://user@web-01.internal:2222
+
+supervisor-rs-client restart child1 on 192.168.1.1 with ~/.ssh/id_ed25519
+
+supervisor-rs-client restart child1 on 192.168.1.1 with ssh-agent
+
+supervisor-rs-client restart child1 on 192.168.1.1 with hmac:/path/to/hmac.secret
+
 Commands:
 
-start/restart/kill/check/stop/kill
+start/restart/kill/check/stop/kill/reopen/logs/history/status/list/diff/keys
 
 more detail:
 https://github.com/ccqpein/supervisor-rs#usage
@@ -138,4 +255,15 @@ mod tests {
 
         assert_eq!(addrs, ["192.168.1.1", "192.168.1.2", "192.168.1.3"])
     }
+
+    #[test]
+    fn json_response_with_server() {
+        let v = json_with_server("127.0.0.1:33889", r#"{"status":"ok","op":"status"}"#);
+        assert_eq!(v["server"], "127.0.0.1:33889");
+        assert_eq!(v["op"], "status");
+
+        let v = json_with_server("127.0.0.1:33889", "start child1 success");
+        assert_eq!(v["status"], "error");
+        assert_eq!(v["error"]["message"], "start child1 success");
+    }
 }
@@ -23,7 +23,7 @@ fn main() {
     let k = match k_result {
         Ok(k) => k,
         Err(e) => {
-            println!("{}", logger::timelog(&e.to_string()));
+            logger::error(&e.to_string(), &[]);
             return;
         }
     };
@@ -35,7 +35,11 @@ fn main() {
     let kg = Arc::new(Mutex::new(k));
 
     //use an additional thread to handle deamon, and send message out.
-    let _ = thread::spawn(move || server::start_deamon(kg, tx));
+    let _ = thread::spawn(move || {
+        if let Err(e) = server::start_deamon(kg, tx) {
+            logger::error("cannot start listener", &[("error", &e.to_string())]);
+        }
+    });
 
     //handle message
     for (f, _) in rx {
@@ -1,27 +1,43 @@
 //! child is the mod of children config
+pub mod child_history;
 pub mod child_hook;
+pub mod child_log;
 pub mod child_output;
 pub mod child_repeat;
+pub mod child_state;
+pub mod child_syslog;
 
 use super::logger;
 use chrono::prelude::*;
+use openssl::sha::sha256;
 use std::fmt;
 use std::fs::File;
 use std::io::{Error as ioError, ErrorKind, Read, Result};
 use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
 use std::time;
 use yaml_rust::YamlLoader;
 
 use child_hook::Hooks;
+use child_log::LogFile;
 use child_output::Output;
 use child_repeat::Repeat;
 
 /// Child config struct
 #[derive(Debug)]
 pub struct Config {
+    /// child name, which is the filename of this config
+    pub name: String,
+
     /// location of this config
     pub location_path: String,
 
+    /// path of config file
+    pub config_file: String,
+
+    /// sha256 of config fields when it was read
+    pub config_hash: Option<String>,
+
     /// command child
     comm: String,
 
@@ -31,6 +47,9 @@ pub struct Config {
     /// stderr of this child
     pub stderr: Option<Output>,
 
+    /// merge stderr into stdout, stderr field is ignored if it is true
+    pub redirect_stderr: bool,
+
     /// repeat feature
     repeat: Option<Repeat>,
 
@@ -41,19 +60,27 @@ pub struct Config {
     pub child_id: Option<u32>,
 
     pub start_time: Option<DateTime<Local>>,
+
+    /// log files written by server
+    pub log_files: Vec<Arc<Mutex<LogFile>>>,
 }
 
 impl Config {
     pub fn new(comm: String) -> Self {
         Config {
+            name: String::new(),
             location_path: String::new(),
+            config_file: String::new(),
+            config_hash: None,
             comm,
             stdout: None,
             stderr: None,
+            redirect_stderr: false,
             child_id: None,
             repeat: None,
             hooks: None,
             start_time: None,
+            log_files: vec![],
         }
     }
 
@@ -75,6 +102,8 @@ impl Config {
                             result.stdout = Some(data);
                         } else if field == "stderr".to_string() {
                             result.stderr = Some(data);
+                        } else if field == "redirect_stderr" {
+                            result.redirect_stderr = true;
                         }
                     }
                 }
@@ -84,7 +113,7 @@ impl Config {
                     Ok(r) => Some(r),
                     Err(e) => {
                         if e.kind() != ErrorKind::NotFound {
-                            println!("{}", logger::timelog(&e.to_string()));
+                            logger::warn(&e.to_string(), &[]);
                         }
                         None
                     }
@@ -95,7 +124,7 @@ impl Config {
                     Ok(h) => Some(h),
                     Err(e) => {
                         if e.kind() != ErrorKind::NotFound {
-                            println!("{}", logger::timelog(&e.to_string()));
+                            logger::warn(&e.to_string(), &[]);
                         }
                         None
                     }
@@ -115,10 +144,18 @@ impl Config {
             Ok(mut cont) => {
                 let _ = cont.read_to_string(&mut string_result);
                 Self::read_from_str(string_result.as_str()).map(|mut c| {
+                    c.name = filepath
+                        .file_name()
+                        .and_then(|f| f.to_str())
+                        .and_then(|f| f.split('.').next())
+                        .unwrap_or("")
+                        .to_string();
                     c.location_path = filepath
                         .parent()
                         .map(|p| p.to_str().unwrap().to_string())
                         .unwrap_or(String::new());
+                    c.config_file = filepath.to_str().unwrap_or("").to_string();
+                    c.config_hash = Some(c.fields_hash());
                     c
                 })
             }
@@ -210,19 +247,102 @@ impl Config {
         }
         None
     }
+
+    /// fields of config written in yaml, without runtime fields like child id
+    pub fn fields(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("command", self.comm.clone()),
+            (
+                "stdout",
+                self.stdout
+                    .as_ref()
+                    .unwrap_or(&Output::new_empty())
+                    .to_string(),
+            ),
+            (
+                "stderr",
+                if self.redirect_stderr {
+                    String::from("redirect to stdout")
+                } else {
+                    self.stderr
+                        .as_ref()
+                        .unwrap_or(&Output::new_empty())
+                        .to_string()
+                },
+            ),
+            (
+                "repeat",
+                self.repeat
+                    .as_ref()
+                    .unwrap_or(&Repeat::new_empty())
+                    .to_string(),
+            ),
+            (
+                "hooks",
+                self.hooks
+                    .as_ref()
+                    .map_or(String::from("none"), |h| h.to_sorted_string()),
+            ),
+        ]
+    }
+
+    /// if config file on disk has changed since this config was read.
+    /// only fields count, whitespace or comments of file do not
+    pub fn config_drifted(&self) -> Result<bool> {
+        let hash = match &self.config_hash {
+            Some(h) => h,
+            None => return Ok(false),
+        };
+
+        let on_disk = Self::read_from_yaml_file(PathBuf::from(&self.config_file))?;
+        Ok(on_disk.config_hash.as_ref() != Some(hash))
+    }
+
+    /// sha256 of fields, hex string
+    fn fields_hash(&self) -> String {
+        let fields = self
+            .fields()
+            .iter()
+            .map(|(k, v)| format!("{}: {}\n", k, v))
+            .collect::<String>();
+        sha256(fields.as_bytes())
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+
+    /// fields different between this config and other one, (field, this, other)
+    pub fn diff(&self, other: &Config) -> Vec<(&'static str, String, String)> {
+        self.fields()
+            .into_iter()
+            .zip(other.fields())
+            .filter(|(a, b)| a.1 != b.1)
+            .map(|(a, b)| (a.0, a.1, b.1))
+            .collect()
+    }
+
+    /// if other config (like the one on disk) differs from this one
+    pub fn differs_from(&self, other: &Config) -> bool {
+        !self.diff(other).is_empty()
+    }
 }
 
 impl Clone for Config {
     fn clone(&self) -> Self {
         Config {
+            name: self.name.clone(),
             location_path: self.location_path.clone(),
+            config_file: self.config_file.clone(),
+            config_hash: self.config_hash.clone(),
             comm: self.comm.clone(),
             stdout: self.stdout.clone(),
             stderr: self.stderr.clone(),
+            redirect_stderr: self.redirect_stderr,
             child_id: self.child_id,
             repeat: self.repeat.clone(),
             hooks: self.hooks.clone(),
             start_time: self.start_time.clone(),
+            log_files: self.log_files.clone(),
         }
     }
 }
@@ -235,7 +355,11 @@ impl fmt::Display for Config {
             self.location_path,
             self.comm,
             self.stdout.as_ref().unwrap_or(&Output::new_empty()),
-            self.stderr.as_ref().unwrap_or(&Output::new_empty()),
+            {if self.redirect_stderr {
+                String::from("redirect to stdout")
+            } else {
+                self.stderr.as_ref().unwrap_or(&Output::new_empty()).to_string()
+            }},
             self.child_id.as_ref().unwrap_or(&(0 as u32)),
             {if let Some(t) = self.start_time{
                 t.format("%Y-%m-%d %H:%M:%S").to_string()
@@ -253,6 +377,7 @@ impl fmt::Display for Config {
 #[cfg(test)]
 mod tests {
     use super::super::server::start_new_child;
+    use super::super::test_util::temp_dir;
     use super::*;
 
     //#[test]
@@ -270,7 +395,10 @@ mod tests {
     fn run_ls() {
         let mut con = dbg!(Config::read_from_yaml_file("./test/ls.yaml".into())).unwrap();
 
-        let _ = dbg!(start_new_child(&mut con));
+        let _ = dbg!(start_new_child(
+            &mut con,
+            Some(Arc::new(Mutex::new(child_log::OutputBuffer::new(1024))))
+        ));
     }
 
     #[test]
@@ -311,4 +439,140 @@ repeat:
 
         println!("whole config is:\n{}", conf);
     }
+
+    #[test]
+    fn test_config_differs() {
+        let running = Config::read_from_str(
+            "
+command: test
+hooks:
+  - prehook: start child1
+  - posthook: start child2
+",
+        )
+        .unwrap();
+        let same = Config::read_from_str(
+            "
+hooks:
+  - posthook: start child2
+  - prehook: start child1
+command: test
+",
+        )
+        .unwrap();
+        let changed = Config::read_from_str(
+            "
+command: test --verbose
+",
+        )
+        .unwrap();
+
+        assert!(!running.differs_from(&same));
+        assert!(running.differs_from(&changed));
+    }
+
+    #[test]
+    fn test_config_drift() {
+        let path = temp_dir("drift").join("drift.yml");
+        std::fs::write(&path, "command: sleep 10\n").unwrap();
+
+        let running = Config::read_from_yaml_file(path.clone()).unwrap();
+        assert!(!running.config_drifted().unwrap());
+
+        // whitespace and comments are not changes
+        std::fs::write(&path, "# sleeps\ncommand:   sleep 10\n\n").unwrap();
+        assert!(!running.config_drifted().unwrap());
+
+        std::fs::write(&path, "command: sleep 20\nrepeat:\n  seconds: 5\n").unwrap();
+        assert!(running.config_drifted().unwrap());
+
+        let on_disk = Config::read_from_yaml_file(path.clone()).unwrap();
+        let diff = running.diff(&on_disk);
+        assert_eq!(diff.len(), 2);
+        assert_eq!(
+            diff[0],
+            ("command", "sleep 10".to_string(), "sleep 20".to_string())
+        );
+        assert_eq!(diff[1].0, "repeat");
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(running.config_drifted().is_err());
+    }
+
+    /// config of child runs shell script in a temp dir, output paths are in the dir
+    fn script_child(name: &str, script: &str, output: &str) -> (Config, PathBuf) {
+        let dir = temp_dir(name);
+        std::fs::write(dir.join("run.sh"), script).unwrap();
+
+        let mut config = Config::read_from_str(&format!(
+            "command: sh {}\noutput:\n{}",
+            dir.join("run.sh").display(),
+            output.replace("DIR", dir.to_str().unwrap())
+        ))
+        .unwrap();
+        config.location_path = dir.to_str().unwrap().to_string();
+        (config, dir)
+    }
+
+    #[test]
+    fn test_unpiped_output() {
+        let (mut config, dir) = script_child(
+            "unpiped",
+            "echo out\necho err >&2\n",
+            "  - stdout: DIR/out.log\n  - stderr: DIR/err.log\n",
+        );
+
+        // no buffer, no rotation: child writes files by itself
+        let mut child = start_new_child(&mut config, None).unwrap();
+        assert!(child.stdout.is_none() && child.stderr.is_none());
+        child.wait().unwrap();
+        assert!(config.log_files.is_empty());
+        assert_eq!(
+            std::fs::read_to_string(dir.join("out.log")).unwrap(),
+            "out\n"
+        );
+        assert_eq!(
+            std::fs::read_to_string(dir.join("err.log")).unwrap(),
+            "err\n"
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_redirect_stderr_order() {
+        let script = "echo out1\necho err1 >&2\necho out2\necho err2 >&2\n";
+        let output = "  - stdout: DIR/out.log\n  - redirect_stderr: true\n";
+        let expected = "out1\nerr1\nout2\nerr2\n";
+
+        // piped to server, because of output buffer
+        let (mut config, dir) = script_child("merged-piped", script, output);
+        let buffer = Arc::new(Mutex::new(child_log::OutputBuffer::new(1024)));
+        let mut child = start_new_child(&mut config, Some(Arc::clone(&buffer))).unwrap();
+        assert!(child.stderr.is_none());
+        child.wait().unwrap();
+
+        // server writes file in another thread
+        let log = dir.join("out.log");
+        for _ in 0..100 {
+            if std::fs::read_to_string(&log).unwrap() == expected {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(20));
+        }
+        assert_eq!(std::fs::read_to_string(&log).unwrap(), expected);
+        let tail = buffer.lock().unwrap().tail(child_log::Stream::Stdout, 4);
+        assert_eq!(String::from_utf8_lossy(&tail), expected);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // child writes file by itself
+        let (mut config, dir) = script_child("merged-file", script, output);
+        let mut child = start_new_child(&mut config, None).unwrap();
+        child.wait().unwrap();
+        assert_eq!(
+            std::fs::read_to_string(dir.join("out.log")).unwrap(),
+            expected
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
@@ -0,0 +1,150 @@
+//! child_history keeps lifecycle events of child, even after child is gone
+use chrono::prelude::*;
+use std::collections::VecDeque;
+use std::fmt;
+use std::os::unix::process::ExitStatusExt;
+use std::process::ExitStatus;
+
+/// default number of events kept for each child
+pub const DEFAULT_HISTORY_SIZE: usize = 50;
+
+/// Lifecycle event of child
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    Started {
+        pid: u32,
+        by: String,
+    },
+    Stopped {
+        pid: u32,
+        by: String,
+    },
+    Restarted {
+        old_pid: u32,
+        pid: u32,
+        by: String,
+    },
+
+    /// child exits by itself, with exit code or signal
+    Exited {
+        pid: u32,
+        code: Option<i32>,
+        signal: Option<i32>,
+    },
+
+    /// hook like "prehook" or "posthook" fired with its command
+    HookFired {
+        hook: String,
+        command: String,
+    },
+}
+
+impl Event {
+    pub fn exited(pid: u32, status: ExitStatus) -> Self {
+        Event::Exited {
+            pid,
+            code: status.code(),
+            signal: status.signal(),
+        }
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Event::Started { pid, by } => write!(f, "started, pid {}, by {}", pid, by),
+            Event::Stopped { pid, by } => write!(f, "stopped, pid {}, by {}", pid, by),
+            Event::Restarted { old_pid, pid, by } => {
+                write!(f, "restarted, pid {} -> {}, by {}", old_pid, pid, by)
+            }
+            Event::Exited { pid, code, signal } => match (code, signal) {
+                (Some(c), _) => write!(f, "exited, pid {}, exit code {}", pid, c),
+                (None, Some(s)) => write!(f, "exited, pid {}, killed by signal {}", pid, s),
+                (None, None) => write!(f, "exited, pid {}", pid),
+            },
+            Event::HookFired { hook, command } => write!(f, "{} fired: {}", hook, command),
+        }
+    }
+}
+
+/// Bounded history of one child, oldest events are dropped
+#[derive(Debug)]
+pub struct History {
+    events: VecDeque<(DateTime<Local>, Event)>,
+    capacity: usize,
+}
+
+impl History {
+    pub fn new(capacity: usize) -> Self {
+        History {
+            events: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    pub fn push(&mut self, event: Event) {
+        if self.capacity == 0 {
+            return;
+        }
+        while self.events.len() >= self.capacity {
+            self.events.pop_front();
+        }
+        self.events.push_back((Local::now(), event));
+    }
+
+    pub fn events(&self) -> impl Iterator<Item = &(DateTime<Local>, Event)> {
+        self.events.iter()
+    }
+
+    /// last event, for status of child
+    pub fn last(&self) -> Option<&Event> {
+        self.events.back().map(|(_, e)| e)
+    }
+}
+
+impl fmt::Display for History {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (time, event) in &self.events {
+            writeln!(f, "[{}] {}", time.format("%Y-%m-%d %H:%M:%S"), event)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_history_bounded() {
+        let mut h = History::new(2);
+        h.push(Event::Started {
+            pid: 1,
+            by: "client".to_string(),
+        });
+        h.push(Event::Exited {
+            pid: 1,
+            code: Some(1),
+            signal: None,
+        });
+        h.push(Event::Exited {
+            pid: 2,
+            code: None,
+            signal: Some(9),
+        });
+
+        let lines = h.to_string();
+        assert_eq!(h.events().count(), 2);
+        assert!(!lines.contains("started"));
+        assert!(lines.contains("] exited, pid 1, exit code 1\n"));
+        assert!(lines.ends_with("] exited, pid 2, killed by signal 9\n"));
+        assert_eq!(
+            h.last(),
+            Some(&Event::Exited {
+                pid: 2,
+                code: None,
+                signal: Some(9)
+            })
+        );
+    }
+}
@@ -82,6 +82,21 @@ impl Hooks {
         }
         None
     }
+
+    /// all hooks sorted by key, like "posthook: start child2, prehook: start child1"
+    pub fn to_sorted_string(&self) -> String {
+        let mut hooks = self
+            .hook_table
+            .iter()
+            .map(|(k, v)| format!("{}: {}", k, v))
+            .collect::<Vec<String>>();
+        hooks.sort();
+        if hooks.is_empty() {
+            String::from("none")
+        } else {
+            hooks.join(", ")
+        }
+    }
 }
 
 impl Clone for Hooks {
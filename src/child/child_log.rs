@@ -0,0 +1,576 @@
+//! child_log is the server side handler of children output.
+//! Server owns the pipes of children it needs to read, keeps recent output in
+//! memory if `log_buffer_size` is given, and writes output files (with rotation
+//! or prefix) by itself.
+use super::super::logger;
+use super::child_output::{Output, OutputMode, RotatePeriod};
+use super::child_syslog::JournalWriter;
+use chrono::prelude::*;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Result, Write};
+use std::path::Path;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// Log file with rotation, written by server
+#[derive(Debug)]
+pub struct LogFile {
+    output: Output,
+    file: File,
+
+    /// current size of file
+    size: u64,
+
+    /// the day this file opened, for daily rotation
+    opened_day: NaiveDate,
+}
+
+impl LogFile {
+    /// open log file depend on output config.
+    /// create mode rotates the old file instead of truncating it
+    pub fn open(output: &Output) -> Result<Self> {
+        if let OutputMode::Create = output.mode {
+            if !output.need_rotation() {
+                File::create(&output.path)?;
+            } else if fs::metadata(&output.path).map(|m| m.len()).unwrap_or(0) > 0 {
+                rotate_files(output)?;
+            }
+        }
+
+        let file = open_append(&output.path)?;
+        let size = file.metadata()?.len();
+
+        Ok(LogFile {
+            output: output.clone(),
+            file,
+            size,
+            opened_day: Local::now().date_naive(),
+        })
+    }
+
+    /// reopen file at the same path, used after external logrotate moved the file
+    pub fn reopen(&mut self) -> Result<()> {
+        self.file = open_append(&self.output.path)?;
+        self.size = self.file.metadata()?.len();
+        Ok(())
+    }
+
+    /// path of this log file
+    pub fn path(&self) -> &str {
+        &self.output.path
+    }
+
+    /// file itself, for child writes it without server
+    pub fn into_file(self) -> File {
+        self.file
+    }
+
+    fn need_rotate(&self, incoming: usize) -> bool {
+        if let Some(max) = self.output.max_size {
+            if self.size > 0 && self.size + incoming as u64 > max {
+                return true;
+            }
+        }
+
+        if let Some(RotatePeriod::Daily) = self.output.rotate {
+            if self.opened_day != Local::now().date_naive() {
+                return true;
+            }
+        }
+
+        false
+    }
+
+    fn rotate(&mut self) -> Result<()> {
+        self.file.flush()?;
+        rotate_files(&self.output)?;
+        self.file = open_append(&self.output.path)?;
+        self.size = 0;
+        self.opened_day = Local::now().date_naive();
+        Ok(())
+    }
+}
+
+impl Write for LogFile {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        if self.need_rotate(buf.len()) {
+            self.rotate()?;
+        }
+
+        let n = self.file.write(buf)?;
+        self.size += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.file.flush()
+    }
+}
+
+fn open_append(path: &str) -> Result<File> {
+    OpenOptions::new().append(true).create(true).open(path)
+}
+
+/// name of the nth rotated file
+fn rotated_path(output: &Output, n: usize) -> String {
+    if output.compress {
+        format!("{}.{}.gz", output.path, n)
+    } else {
+        format!("{}.{}", output.path, n)
+    }
+}
+
+/// shift path.1 -> path.2 ... then move path to path.1 (and gzip it).
+/// the oldest one beyond max_files is deleted.
+/// nothing to rotate if path is moved or deleted by others, it is opened again after
+fn rotate_files(output: &Output) -> Result<()> {
+    if output.max_files == 0 {
+        return File::create(&output.path).map(|_| ());
+    }
+    if !Path::new(&output.path).exists() {
+        return Ok(());
+    }
+
+    let oldest = rotated_path(output, output.max_files);
+    if Path::new(&oldest).exists() {
+        fs::remove_file(&oldest)?;
+    }
+
+    for n in (1..output.max_files).rev() {
+        let from = rotated_path(output, n);
+        if Path::new(&from).exists() {
+            fs::rename(&from, rotated_path(output, n + 1))?;
+        }
+    }
+
+    if output.compress {
+        let mut src = File::open(&output.path)?;
+        let mut encoder = GzEncoder::new(
+            File::create(rotated_path(output, 1))?,
+            Compression::default(),
+        );
+        io::copy(&mut src, &mut encoder)?;
+        encoder.finish()?;
+        fs::remove_file(&output.path)
+    } else {
+        fs::rename(&output.path, rotated_path(output, 1))
+    }
+}
+
+/// longest partial line kept before it is written out anyway
+const MAX_LINE_LENGTH: usize = 8192;
+
+/// Split output to lines and prefix each line with timestamp, child name and stream.
+/// Bytes are kept as they are, so binary output won't be corrupted.
+#[derive(Debug)]
+pub struct LinePrefixer {
+    /// None means only split lines
+    prefix: Option<String>,
+
+    /// partial line waiting for newline
+    pending: Vec<u8>,
+}
+
+impl LinePrefixer {
+    pub fn new(name: &str, stream: Stream) -> Self {
+        LinePrefixer {
+            prefix: Some(format!("{} {}: ", name, stream)),
+            pending: vec![],
+        }
+    }
+
+    /// only split lines, without prefix
+    pub fn plain() -> Self {
+        LinePrefixer {
+            prefix: None,
+            pending: vec![],
+        }
+    }
+
+    /// feed data read from pipe, return complete lines with prefix
+    pub fn feed(&mut self, data: &[u8]) -> Vec<Vec<u8>> {
+        let mut result = vec![];
+        for b in data {
+            self.pending.push(*b);
+            if *b == b'\n' || self.pending.len() >= MAX_LINE_LENGTH {
+                result.push(self.take_line());
+            }
+        }
+        result
+    }
+
+    /// return last partial line when pipe closed
+    pub fn finish(&mut self) -> Option<Vec<u8>> {
+        if self.pending.is_empty() {
+            return None;
+        }
+        Some(self.take_line())
+    }
+
+    fn take_line(&mut self) -> Vec<u8> {
+        let mut line = match &self.prefix {
+            Some(p) => logger::timelog_rfc3339(p).into_bytes(),
+            None => vec![],
+        };
+        line.append(&mut self.pending);
+        if line.last() != Some(&b'\n') {
+            line.push(b'\n');
+        }
+        line
+    }
+}
+
+/// Which stream of child
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+impl fmt::Display for Stream {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Stream::Stdout => write!(f, "out"),
+            Stream::Stderr => write!(f, "err"),
+        }
+    }
+}
+
+/// Ring buffer keeps last `capacity` bytes
+#[derive(Debug)]
+pub struct RingBuffer {
+    data: VecDeque<u8>,
+    capacity: usize,
+}
+
+impl RingBuffer {
+    pub fn new(capacity: usize) -> Self {
+        RingBuffer {
+            data: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub fn push(&mut self, bytes: &[u8]) {
+        let bytes = if bytes.len() > self.capacity {
+            &bytes[bytes.len() - self.capacity..]
+        } else {
+            bytes
+        };
+
+        let overflow = (self.data.len() + bytes.len()).saturating_sub(self.capacity);
+        self.data.drain(..overflow);
+        self.data.extend(bytes);
+    }
+
+    /// last n lines in buffer
+    pub fn tail(&self, lines: usize) -> Vec<u8> {
+        let mut count = 0;
+        let mut start = 0;
+        for (i, b) in self.data.iter().enumerate().rev() {
+            // the newline at the end doesn't count
+            if *b == b'\n' && i != self.data.len() - 1 {
+                count += 1;
+                if count == lines {
+                    start = i + 1;
+                    break;
+                }
+            }
+        }
+
+        self.data.iter().skip(start).cloned().collect()
+    }
+}
+
+/// Recent output of a child. It is kept by child name, so
+/// output of last exited instance is still here after child stopped.
+#[derive(Debug)]
+pub struct OutputBuffer {
+    stdout: RingBuffer,
+    stderr: RingBuffer,
+
+    /// clients following output, dropped when receiver gone
+    followers: Vec<(Stream, Sender<Vec<u8>>)>,
+}
+
+impl OutputBuffer {
+    pub fn new(capacity: usize) -> Self {
+        OutputBuffer {
+            stdout: RingBuffer::new(capacity),
+            stderr: RingBuffer::new(capacity),
+            followers: vec![],
+        }
+    }
+
+    pub fn push(&mut self, stream: Stream, bytes: &[u8]) {
+        match stream {
+            Stream::Stdout => self.stdout.push(bytes),
+            Stream::Stderr => self.stderr.push(bytes),
+        }
+
+        self.followers
+            .retain(|(s, tx)| *s != stream || tx.send(bytes.to_vec()).is_ok());
+    }
+
+    /// mark a new instance of child starts
+    pub fn mark_start(&mut self, pid: u32) {
+        let mark = logger::timelog(&format!("==== start new instance, pid {} ====\n", pid));
+        self.push(Stream::Stdout, mark.as_bytes());
+        self.push(Stream::Stderr, mark.as_bytes());
+    }
+
+    /// return last lines and a receiver of all new output after them
+    pub fn follow(&mut self, stream: Stream, lines: usize) -> (Vec<u8>, Receiver<Vec<u8>>) {
+        let (tx, rx) = channel();
+        self.followers.push((stream, tx));
+        (self.tail(stream, lines), rx)
+    }
+
+    pub fn tail(&self, stream: Stream, lines: usize) -> Vec<u8> {
+        match stream {
+            Stream::Stdout => self.stdout.tail(lines),
+            Stream::Stderr => self.stderr.tail(lines),
+        }
+    }
+}
+
+/// Where the output of child's pipe goes
+#[derive(Debug)]
+pub enum Sink {
+    /// output file, with prefixer if prefix is on
+    File(Arc<Mutex<LogFile>>, Option<LinePrefixer>),
+
+    /// in memory buffer
+    Buffer(Arc<Mutex<OutputBuffer>>, Stream),
+
+    /// syslog or journald
+    Journal(JournalWriter, LinePrefixer),
+
+    /// stdout of server itself, when no output file given
+    ServerStdout,
+
+    /// stderr of server itself, when no output file given
+    ServerStderr,
+}
+
+/// where sink writes, for log
+impl fmt::Display for Sink {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Sink::File(log, _) => write!(f, "{}", log.lock().unwrap().path()),
+            Sink::Buffer(_, stream) => write!(f, "buffer of std{}", stream),
+            Sink::Journal(w, _) => write!(f, "{}", w.target()),
+            Sink::ServerStdout => write!(f, "stdout of server"),
+            Sink::ServerStderr => write!(f, "stderr of server"),
+        }
+    }
+}
+
+impl Sink {
+    fn write(&mut self, data: &[u8]) -> Result<()> {
+        match self {
+            Sink::File(log, prefixer) => match prefixer {
+                Some(p) => write_chunks(log, &p.feed(data)),
+                None => write_chunks(log, &[data.to_vec()]),
+            },
+            Sink::Buffer(buf, stream) => {
+                buf.lock().unwrap().push(*stream, data);
+                Ok(())
+            }
+            Sink::Journal(w, splitter) => {
+                for line in splitter.feed(data) {
+                    w.send(&line)?;
+                }
+                Ok(())
+            }
+            Sink::ServerStdout => io::stdout().lock().write_all(data),
+            Sink::ServerStderr => io::stderr().lock().write_all(data),
+        }
+    }
+
+    /// pipe closed, write the rest
+    fn finish(&mut self) {
+        match self {
+            Sink::File(log, Some(p)) => {
+                if let Some(last) = p.finish() {
+                    let _ = write_chunks(log, &[last]);
+                }
+            }
+            Sink::Journal(w, splitter) => {
+                if let Some(last) = splitter.finish() {
+                    let _ = w.send(&last);
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+/// keep reading from child's pipe and write to all sinks until pipe closed.
+/// sink failed writing is dropped with an error log, others keep going
+pub fn pipe_to<R: Read + Send + 'static>(
+    name: &str,
+    mut reader: R,
+    mut sinks: Vec<Sink>,
+) -> thread::JoinHandle<()> {
+    let name = name.to_string();
+    thread::spawn(move || {
+        let mut buf = [0; 8192];
+        loop {
+            let n = match reader.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(_) => break,
+            };
+
+            sinks.retain_mut(|sink| match sink.write(&buf[..n]) {
+                Ok(_) => true,
+                Err(e) => {
+                    logger::error(
+                        "output of child is not written anymore",
+                        &[
+                            ("child", &name),
+                            ("to", &sink.to_string()),
+                            ("error", &e.to_string()),
+                        ],
+                    );
+                    false
+                }
+            });
+        }
+
+        sinks.iter_mut().for_each(|sink| sink.finish());
+    })
+}
+
+/// each chunk is written by one write call, so lines from different children
+/// appending the same file won't mix up
+fn write_chunks(log: &Arc<Mutex<LogFile>>, chunks: &[Vec<u8>]) -> Result<()> {
+    let mut log = log.lock().unwrap();
+    for chunk in chunks {
+        log.write_all(chunk)?;
+    }
+    log.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::test_util::temp_dir;
+    use super::*;
+
+    fn test_output(name: &str) -> Output {
+        let dir = temp_dir(name);
+
+        let mut output = Output::new_empty();
+        output.path = dir.join("out.log").to_str().unwrap().to_string();
+        output
+    }
+
+    #[test]
+    fn test_rotate_by_size() {
+        let mut output = test_output("size");
+        output.max_size = Some(10);
+        output.max_files = 2;
+
+        let mut log = LogFile::open(&output).unwrap();
+        for _ in 0..4 {
+            log.write_all(b"123456789\n").unwrap();
+        }
+
+        assert_eq!(fs::read_to_string(&output.path).unwrap(), "123456789\n");
+        assert!(Path::new(&rotated_path(&output, 1)).exists());
+        assert!(Path::new(&rotated_path(&output, 2)).exists());
+        assert!(!Path::new(&rotated_path(&output, 3)).exists());
+    }
+
+    #[test]
+    fn test_rotate_deleted_file() {
+        let mut output = test_output("deleted");
+        output.max_size = Some(10);
+        output.max_files = 2;
+
+        let mut log = LogFile::open(&output).unwrap();
+        log.write_all(b"123456789\n").unwrap();
+        fs::remove_file(&output.path).unwrap();
+
+        // nothing to rotate, file is opened again
+        log.write_all(b"abcdefghi\n").unwrap();
+        assert_eq!(fs::read_to_string(&output.path).unwrap(), "abcdefghi\n");
+        assert!(!Path::new(&rotated_path(&output, 1)).exists());
+    }
+
+    #[test]
+    fn test_line_prefixer() {
+        let mut p = LinePrefixer::new("child1", Stream::Stdout);
+
+        // partial line is kept
+        assert!(p.feed(b"hello ").is_empty());
+
+        let lines = p.feed(b"world\n\xff\x00bin\nlast");
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].ends_with(b"] child1 out: hello world\n"));
+        assert!(lines[1].ends_with(b"] child1 out: \xff\x00bin\n"));
+
+        let last = p.finish().unwrap();
+        assert!(last.ends_with(b"] child1 out: last\n"));
+        assert!(p.finish().is_none());
+
+        let mut p = LinePrefixer::plain();
+        assert_eq!(p.feed(b"a\nb"), vec![b"a\n".to_vec()]);
+        assert_eq!(p.finish(), Some(b"b\n".to_vec()));
+    }
+
+    #[test]
+    fn test_ring_buffer() {
+        let mut rb = RingBuffer::new(16);
+        rb.push(b"line1\nline2\n");
+        assert_eq!(rb.tail(1), b"line2\n");
+        assert_eq!(rb.tail(5), b"line1\nline2\n");
+
+        // oldest bytes are dropped
+        rb.push(b"line3\nline4\n");
+        assert_eq!(rb.tail(10), b"ne2\nline3\nline4\n");
+
+        rb.push(b"a very long line more than capacity\n");
+        assert_eq!(rb.tail(1), b"e than capacity\n");
+    }
+
+    #[test]
+    fn test_follow_output() {
+        let mut buffer = OutputBuffer::new(1024);
+        buffer.push(Stream::Stdout, b"old\n");
+
+        let (snapshot, rx) = buffer.follow(Stream::Stdout, 10);
+        assert_eq!(snapshot, b"old\n");
+
+        buffer.push(Stream::Stderr, b"err\n");
+        buffer.push(Stream::Stdout, b"new\n");
+        assert_eq!(rx.try_recv().unwrap(), b"new\n");
+        assert!(rx.try_recv().is_err());
+
+        // follower is dropped after receiver gone
+        drop(rx);
+        buffer.push(Stream::Stdout, b"more\n");
+        assert!(buffer.followers.is_empty());
+    }
+
+    #[test]
+    fn test_create_mode_keeps_history() {
+        let mut output = test_output("create");
+        output.max_size = Some(1024);
+        output.compress = true;
+
+        fs::write(&output.path, "last run\n").unwrap();
+        let mut log = LogFile::open(&output).unwrap();
+        log.write_all(b"this run\n").unwrap();
+
+        assert_eq!(fs::read_to_string(&output.path).unwrap(), "this run\n");
+        assert!(Path::new(&rotated_path(&output, 1)).exists());
+    }
+}
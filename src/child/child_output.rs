@@ -1,3 +1,4 @@
+use super::child_syslog::facility_code;
 use std::fmt;
 use std::io::{Error as ioError, ErrorKind, Result};
 use yaml_rust::Yaml;
@@ -9,11 +10,77 @@ pub enum OutputMode {
     Append,
 }
 
+/// Where output goes. File path, syslog or journald
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum OutputTarget {
+    File,
+    Syslog,
+    Journald,
+}
+
+/// Rotate period of output file. Only daily so far
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum RotatePeriod {
+    Daily,
+}
+
+/// Default number of rotated files to keep
+const DEFAULT_MAX_FILES: usize = 5;
+
 /// Output struct. Path and output mode
+///
+/// Output file is written by server. If any of `max_size` or `rotate`
+/// is given, server rotates the file.
+///
+/// ```yaml
+/// output:
+///   - stdout: out.log
+///     mode: append
+///     max_size: 10M
+///     max_files: 3
+///     rotate: daily
+///     compress: true
+///     prefix: true
+///   - redirect_stderr: true # or "- stderr: stdout"
+/// ```
+///
+/// `syslog` and `journald` are special paths, output goes to them line by line:
+///
+/// ```yaml
+/// output:
+///   - stdout: syslog
+///     facility: local0
+///     tag: myapp
+///   - stderr: journald
+/// ```
 #[derive(Debug, Clone)]
 pub struct Output {
     pub path: String,
     pub mode: OutputMode,
+
+    /// file, syslog or journald
+    pub target: OutputTarget,
+
+    /// syslog facility name, default is user
+    pub facility: String,
+
+    /// syslog identifier, default is child name
+    pub tag: Option<String>,
+
+    /// rotate file when it becomes bigger than max_size bytes
+    pub max_size: Option<u64>,
+
+    /// how many rotated files keep
+    pub max_files: usize,
+
+    /// rotate file by time
+    pub rotate: Option<RotatePeriod>,
+
+    /// gzip rotated files
+    pub compress: bool,
+
+    /// prefix each line with timestamp, child name and stream
+    pub prefix: bool,
 }
 
 impl Output {
@@ -21,9 +88,32 @@ impl Output {
         Output {
             path: String::new(),
             mode: OutputMode::Create,
+            target: OutputTarget::File,
+            facility: "user".to_string(),
+            tag: None,
+            max_size: None,
+            max_files: DEFAULT_MAX_FILES,
+            rotate: None,
+            compress: false,
+            prefix: false,
         }
     }
 
+    /// if file need rotation by size or time
+    pub fn need_rotation(&self) -> bool {
+        self.max_size.is_some() || self.rotate.is_some()
+    }
+
+    /// "syslog" and "journald" are special paths
+    fn set_path(&mut self, path: &str) {
+        self.target = match path {
+            "syslog" => OutputTarget::Syslog,
+            "journald" => OutputTarget::Journald,
+            _ => OutputTarget::File,
+        };
+        self.path = path.to_string();
+    }
+
     pub fn new(input: Yaml) -> Result<Vec<(String, Self)>> {
         let lst = match input.into_vec() {
             Some(lst) => lst,
@@ -38,13 +128,7 @@ impl Output {
         let mut result = vec![];
 
         for hash in lst {
-            let mut temp = (
-                String::new(),
-                Self {
-                    path: String::new(),
-                    mode: OutputMode::Create,
-                },
-            );
+            let mut temp = (String::new(), Self::new_empty());
             for (p, m) in hash.into_hash().unwrap().iter() {
                 match p.as_str() {
                     Some("mode") => match m.as_str() {
@@ -52,9 +136,74 @@ impl Output {
                         Some("append") => temp.1.mode = OutputMode::Append,
                         _ => (),
                     },
+                    Some("max_size") => temp.1.max_size = Some(parse_size(m)?),
+                    Some("max_files") => match m.as_i64() {
+                        Some(n) if n >= 0 => temp.1.max_files = n as usize,
+                        _ => {
+                            return Err(ioError::new(
+                                ErrorKind::InvalidData,
+                                "max_files should be 0 or a positive number",
+                            ));
+                        }
+                    },
+                    Some("rotate") => match m.as_str() {
+                        Some("daily") => temp.1.rotate = Some(RotatePeriod::Daily),
+                        _ => {
+                            return Err(ioError::new(
+                                ErrorKind::InvalidData,
+                                "rotate only support daily",
+                            ));
+                        }
+                    },
+                    Some("compress") => match m.as_bool() {
+                        Some(b) => temp.1.compress = b,
+                        None => {
+                            return Err(ioError::new(
+                                ErrorKind::InvalidData,
+                                "compress should be true or false",
+                            ));
+                        }
+                    },
+                    Some("prefix") => match m.as_bool() {
+                        Some(b) => temp.1.prefix = b,
+                        None => {
+                            return Err(ioError::new(
+                                ErrorKind::InvalidData,
+                                "prefix should be true or false",
+                            ));
+                        }
+                    },
+                    Some("facility") => match m.as_str() {
+                        Some(f) if facility_code(f).is_some() => temp.1.facility = f.to_string(),
+                        _ => {
+                            return Err(ioError::new(
+                                ErrorKind::InvalidData,
+                                format!("facility {:?} is illegal", m),
+                            ));
+                        }
+                    },
+                    Some("tag") => match m.as_str() {
+                        Some(t) => temp.1.tag = Some(t.to_string()),
+                        None => {
+                            return Err(ioError::new(
+                                ErrorKind::InvalidData,
+                                "tag should be string",
+                            ));
+                        }
+                    },
+                    Some("redirect_stderr") => match m.as_bool() {
+                        Some(true) => temp.0 = "redirect_stderr".to_string(),
+                        Some(false) => (),
+                        None => {
+                            return Err(ioError::new(
+                                ErrorKind::InvalidData,
+                                "redirect_stderr should be true or false",
+                            ));
+                        }
+                    },
                     Some("stdout") => match m.as_str() {
                         Some(s) => {
-                            temp.1.path = s.to_string();
+                            temp.1.set_path(s);
                             temp.0 = "stdout".to_string()
                         }
                         None => {
@@ -64,9 +213,11 @@ impl Output {
                             ));
                         }
                     },
+                    // "stderr: stdout" means merge stderr into stdout
                     Some("stderr") => match m.as_str() {
+                        Some("stdout") => temp.0 = "redirect_stderr".to_string(),
                         Some(s) => {
-                            temp.1.path = s.to_string();
+                            temp.1.set_path(s);
                             temp.0 = "stderr".to_string()
                         }
                         None => {
@@ -89,14 +240,131 @@ impl Output {
 
         Ok(result)
     }
+
+    /// log file of server itself, always append. Input is a path or
+    /// a hash with `path` and rotation fields:
+    ///
+    /// ```yaml
+    /// audit_log:
+    ///   path: /var/log/supervisor-rs-audit.log
+    ///   max_size: 10M
+    ///   compress: true
+    /// ```
+    pub fn new_log_file(input: &Yaml) -> Result<Self> {
+        let mut output = match input {
+            Yaml::String(path) => {
+                let mut o = Self::new_empty();
+                o.set_path(path);
+                o
+            }
+            Yaml::Hash(hash) => {
+                // same fields as children output, "path" instead of "stdout"
+                let mut hash = hash.clone();
+                let path = hash
+                    .remove(&Yaml::from_str("path"))
+                    .ok_or_else(|| ioError::new(ErrorKind::InvalidData, "log file no path"))?;
+                hash.insert(Yaml::from_str("stdout"), path);
+
+                match Self::new(Yaml::Array(vec![Yaml::Hash(hash)]))?.pop() {
+                    Some((_, o)) => o,
+                    None => return Err(ioError::new(ErrorKind::InvalidData, "log file no path")),
+                }
+            }
+            _ => {
+                return Err(ioError::new(
+                    ErrorKind::InvalidData,
+                    "log file should be path or hash",
+                ))
+            }
+        };
+
+        if output.target != OutputTarget::File {
+            return Err(ioError::new(
+                ErrorKind::InvalidData,
+                format!("log file cannot be {}", output.target),
+            ));
+        }
+
+        output.mode = OutputMode::Append;
+        Ok(output)
+    }
+}
+
+/// parse size like 1024, "512K", "10M" or "1G" to bytes
+fn parse_size(input: &Yaml) -> Result<u64> {
+    if let Some(n) = input.as_i64() {
+        if n > 0 {
+            return Ok(n as u64);
+        }
+    }
+
+    let s = input.as_str().unwrap_or("").trim();
+    let (num, unit) = match s.char_indices().last() {
+        Some((i, 'K')) | Some((i, 'k')) => (&s[..i], 1024),
+        Some((i, 'M')) | Some((i, 'm')) => (&s[..i], 1024 * 1024),
+        Some((i, 'G')) | Some((i, 'g')) => (&s[..i], 1024 * 1024 * 1024),
+        _ => (s, 1),
+    };
+
+    match num.trim().parse::<u64>() {
+        Ok(n) if n > 0 => Ok(n * unit),
+        _ => Err(ioError::new(
+            ErrorKind::InvalidData,
+            format!("max_size {:?} is illegal", input),
+        )),
+    }
 }
 
 impl fmt::Display for Output {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        if self.path == "" {
+        if self.path.is_empty() {
             return write!(f, "none");
         }
-        write!(f, "path: {}, mode: {}", self.path, self.mode)
+        if self.target != OutputTarget::File {
+            write!(f, "{}, facility: {}", self.target, self.facility)?;
+            if let Some(tag) = &self.tag {
+                write!(f, ", tag: {}", tag)?;
+            }
+            return Ok(());
+        }
+
+        write!(f, "path: {}, mode: {}", self.path, self.mode)?;
+
+        if let Some(size) = self.max_size {
+            write!(f, ", max size: {} bytes", size)?;
+        }
+        if let Some(period) = self.rotate {
+            write!(f, ", rotate: {}", period)?;
+        }
+        if self.prefix {
+            write!(f, ", prefix: true")?;
+        }
+        if self.need_rotation() {
+            write!(
+                f,
+                ", max files: {}, compress: {}",
+                self.max_files, self.compress
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for OutputTarget {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OutputTarget::File => write!(f, "file"),
+            OutputTarget::Syslog => write!(f, "syslog"),
+            OutputTarget::Journald => write!(f, "journald"),
+        }
+    }
+}
+
+impl fmt::Display for RotatePeriod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RotatePeriod::Daily => write!(f, "daily"),
+        }
     }
 }
 
@@ -108,3 +376,121 @@ impl fmt::Display for OutputMode {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use yaml_rust::YamlLoader;
+
+    #[test]
+    fn test_parse_rotate_output() {
+        let input = YamlLoader::load_from_str(
+            "
+output:
+  - stdout: out.log
+    mode: append
+    max_size: 10M
+    max_files: 3
+    rotate: daily
+    compress: true
+  - stderr: err.log
+  - stderr: prefixed.log
+    prefix: true
+  - redirect_stderr: true
+  - stderr: stdout
+",
+        )
+        .unwrap();
+
+        let result = Output::new(input[0]["output"].clone()).unwrap();
+        assert_eq!(result[0].0, "stdout");
+        assert_eq!(result[0].1.max_size, Some(10 * 1024 * 1024));
+        assert_eq!(result[0].1.max_files, 3);
+        assert_eq!(result[0].1.rotate, Some(RotatePeriod::Daily));
+        assert!(result[0].1.compress);
+        assert!(result[0].1.need_rotation());
+
+        assert_eq!(result[1].0, "stderr");
+        assert!(!result[1].1.need_rotation());
+
+        assert!(result[2].1.prefix);
+        assert!(!result[2].1.need_rotation());
+
+        assert_eq!(result[3].0, "redirect_stderr");
+        assert_eq!(result[4].0, "redirect_stderr");
+
+        // 0 keeps no rotated file, negative is wrong
+        let input = YamlLoader::load_from_str(
+            "- stdout: a.log\n  max_files: 0\n- stdout: b.log\n  max_files: -1",
+        )
+        .unwrap();
+        let lst = input[0].as_vec().unwrap();
+        let result = Output::new(Yaml::Array(lst[..1].to_vec())).unwrap();
+        assert_eq!(result[0].1.max_files, 0);
+        assert!(Output::new(Yaml::Array(lst[1..].to_vec())).is_err());
+    }
+
+    #[test]
+    fn test_parse_journal_output() {
+        let input = YamlLoader::load_from_str(
+            "
+output:
+  - stdout: syslog
+    facility: local0
+    tag: myapp
+  - stderr: journald
+  - stderr: nothing
+    facility: what
+",
+        )
+        .unwrap();
+
+        let output = input[0]["output"].as_vec().unwrap();
+        let result = Output::new(Yaml::Array(output[..2].to_vec())).unwrap();
+        assert_eq!(result[0].1.target, OutputTarget::Syslog);
+        assert_eq!(result[0].1.facility, "local0");
+        assert_eq!(result[0].1.tag, Some("myapp".to_string()));
+        assert_eq!(result[1].1.target, OutputTarget::Journald);
+        assert_eq!(result[1].1.facility, "user");
+
+        assert!(Output::new(Yaml::Array(output[2..].to_vec())).is_err());
+    }
+
+    #[test]
+    fn test_parse_log_file() {
+        let input = YamlLoader::load_from_str(
+            "
+a: /tmp/audit.log
+b:
+  path: /tmp/audit.log
+  max_size: 1M
+  compress: true
+c:
+  max_size: 1M
+d: syslog
+",
+        )
+        .unwrap();
+
+        let a = Output::new_log_file(&input[0]["a"]).unwrap();
+        assert_eq!(a.path, "/tmp/audit.log");
+        assert!(!a.need_rotation());
+
+        let b = Output::new_log_file(&input[0]["b"]).unwrap();
+        assert_eq!(b.path, "/tmp/audit.log");
+        assert_eq!(b.max_size, Some(1024 * 1024));
+        assert!(b.compress);
+
+        assert!(Output::new_log_file(&input[0]["c"]).is_err());
+        assert!(Output::new_log_file(&input[0]["d"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size(&Yaml::Integer(100)).unwrap(), 100);
+        assert_eq!(parse_size(&Yaml::from_str("2k")).unwrap(), 2048);
+        assert_eq!(parse_size(&Yaml::from_str("1G")).unwrap(), 1 << 30);
+        assert!(parse_size(&Yaml::from_str("0M")).is_err());
+        assert!(parse_size(&Yaml::from_str("ten")).is_err());
+    }
+}
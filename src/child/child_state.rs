@@ -0,0 +1,238 @@
+//! child_state tracks state of each configured child, running or not
+use chrono::prelude::*;
+use std::fmt;
+
+/// child has to keep running this long after spawn to be Running
+pub const START_SECS: i64 = 1;
+
+/// State of child
+///
+/// ```text
+/// Stopped -> Starting -> Running -> Stopping -> Stopped
+///               |           |
+///               |           +-> Exited(code)
+///               +-> Backoff (exits too quickly, timer will start it again)
+///               +-> Fatal (cannot spawn, or exits too quickly)
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChildState {
+    Stopped,
+    Starting,
+    Running,
+    Backoff,
+    Stopping,
+    Exited(Option<i32>),
+    Fatal,
+}
+
+impl fmt::Display for ChildState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ChildState::Stopped => write!(f, "STOPPED"),
+            ChildState::Starting => write!(f, "STARTING"),
+            ChildState::Running => write!(f, "RUNNING"),
+            ChildState::Backoff => write!(f, "BACKOFF"),
+            ChildState::Stopping => write!(f, "STOPPING"),
+            ChildState::Exited(_) => write!(f, "EXITED"),
+            ChildState::Fatal => write!(f, "FATAL"),
+        }
+    }
+}
+
+/// Status of child, state with pid, start time and last exit
+#[derive(Debug, Clone)]
+pub struct Status {
+    pub state: ChildState,
+    pub pid: Option<u32>,
+    pub start_time: Option<DateTime<Local>>,
+
+    /// exit code or "signal N" of last exit
+    pub last_exit: Option<String>,
+
+    /// if config file changed since child started, only for running child
+    pub config_changed: Option<String>,
+}
+
+impl Status {
+    pub fn new() -> Self {
+        Status {
+            state: ChildState::Stopped,
+            pid: None,
+            start_time: None,
+            last_exit: None,
+            config_changed: None,
+        }
+    }
+
+    pub fn starting(&mut self) {
+        self.state = ChildState::Starting;
+    }
+
+    /// child spawned, it stays Starting until START_SECS passed
+    pub fn started(&mut self, pid: u32, time: DateTime<Local>) {
+        self.state = ChildState::Starting;
+        self.pid = Some(pid);
+        self.start_time = Some(time);
+    }
+
+    pub fn spawn_failed(&mut self) {
+        self.state = ChildState::Fatal;
+        self.pid = None;
+        self.start_time = None;
+    }
+
+    pub fn stopping(&mut self) {
+        self.state = ChildState::Stopping;
+    }
+
+    pub fn stopped(&mut self) {
+        self.state = ChildState::Stopped;
+        self.pid = None;
+        self.start_time = None;
+    }
+
+    /// child exits by itself. will_retry means timer will start it again
+    pub fn exited(&mut self, code: Option<i32>, signal: Option<i32>, will_retry: bool) {
+        self.refresh();
+        self.state = match self.state {
+            ChildState::Starting if will_retry => ChildState::Backoff,
+            ChildState::Starting => ChildState::Fatal,
+            _ => ChildState::Exited(code),
+        };
+        self.last_exit = match (code, signal) {
+            (Some(c), _) => Some(c.to_string()),
+            (None, Some(s)) => Some(format!("signal {}", s)),
+            (None, None) => None,
+        };
+        self.pid = None;
+        self.start_time = None;
+    }
+
+    /// Starting becomes Running after START_SECS
+    pub fn refresh(&mut self) {
+        if self.state != ChildState::Starting {
+            return;
+        }
+        if let Some(t) = self.start_time {
+            if (Local::now() - t).num_seconds() >= START_SECS {
+                self.state = ChildState::Running;
+            }
+        }
+    }
+
+    /// uptime like "1:02:03"
+    pub fn uptime(&self) -> Option<String> {
+        self.start_time.map(|t| {
+            let secs = (Local::now() - t).num_seconds().max(0);
+            format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+        })
+    }
+}
+
+impl Default for Status {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// table of (name, status), like "supervisorctl status"
+pub fn status_table(rows: &[(String, Status)]) -> String {
+    let mut cells = vec![vec![
+        "NAME".to_string(),
+        "STATE".to_string(),
+        "PID".to_string(),
+        "UPTIME".to_string(),
+        "LAST EXIT".to_string(),
+        "CONFIG CHANGED".to_string(),
+    ]];
+    for (name, status) in rows {
+        cells.push(vec![
+            name.clone(),
+            status.state.to_string(),
+            status.pid.map_or("-".to_string(), |p| p.to_string()),
+            status.uptime().unwrap_or_else(|| "-".to_string()),
+            status.last_exit.clone().unwrap_or_else(|| "-".to_string()),
+            status
+                .config_changed
+                .clone()
+                .unwrap_or_else(|| "-".to_string()),
+        ]);
+    }
+
+    format_table(&cells)
+}
+
+/// align columns of cells, first row is header
+pub fn format_table(cells: &[Vec<String>]) -> String {
+    let mut widths = vec![0; cells.first().map_or(0, |r| r.len())];
+    for row in cells {
+        for (i, cell) in row.iter().enumerate() {
+            widths[i] = widths[i].max(cell.len());
+        }
+    }
+
+    let mut table = String::new();
+    for row in cells {
+        let line = row
+            .iter()
+            .enumerate()
+            .map(|(i, cell)| format!("{:width$}", cell, width = widths[i]))
+            .collect::<Vec<String>>()
+            .join("  ");
+        table.push_str(line.trim_end());
+        table.push('\n');
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    #[test]
+    fn test_state_transitions() {
+        let mut s = Status::new();
+        s.starting();
+        s.started(42, Local::now());
+        assert_eq!(s.state, ChildState::Starting);
+        s.exited(Some(1), None, true);
+        assert_eq!(s.state, ChildState::Backoff);
+
+        s.started(43, Local::now());
+        s.exited(None, Some(9), false);
+        assert_eq!(s.state, ChildState::Fatal);
+        assert_eq!(s.last_exit, Some("signal 9".to_string()));
+
+        s.started(44, Local::now() - Duration::seconds(3661));
+        s.refresh();
+        assert_eq!(s.state, ChildState::Running);
+        assert_eq!(s.uptime(), Some("1:01:01".to_string()));
+        s.exited(Some(3), None, true);
+        assert_eq!(s.state, ChildState::Exited(Some(3)));
+        assert_eq!(s.pid, None);
+
+        s.stopping();
+        s.stopped();
+        assert_eq!(s.state, ChildState::Stopped);
+    }
+
+    #[test]
+    fn test_status_table() {
+        let mut running = Status::new();
+        running.started(1234, Local::now() - Duration::seconds(5));
+        running.refresh();
+        running.config_changed = Some("yes".to_string());
+        let mut exited = Status::new();
+        exited.started(1, Local::now() - Duration::seconds(5));
+        exited.exited(Some(3), None, false);
+
+        let table = status_table(&[("child1".to_string(), running), ("c2".to_string(), exited)]);
+        assert_eq!(
+            table,
+            "NAME    STATE    PID   UPTIME   LAST EXIT  CONFIG CHANGED\n\
+             child1  RUNNING  1234  0:00:05  -          yes\n\
+             c2      EXITED   -     -        3          -\n"
+        );
+    }
+}
@@ -0,0 +1,218 @@
+//! child_syslog sends children output to syslog or journald, line by line
+use super::child_log::Stream;
+use super::child_output::{Output, OutputTarget};
+use chrono::prelude::*;
+use std::io::{Error as ioError, ErrorKind, Result};
+use std::os::unix::net::UnixDatagram;
+
+/// local syslog socket
+pub const SYSLOG_SOCKET: &str = "/dev/log";
+
+/// journald native protocol socket
+pub const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// severity of stdout lines
+const LOG_INFO: u8 = 6;
+
+/// severity of stderr lines
+const LOG_ERR: u8 = 3;
+
+/// all syslog facilities by name
+const FACILITIES: [(&str, u8); 20] = [
+    ("kern", 0),
+    ("user", 1),
+    ("mail", 2),
+    ("daemon", 3),
+    ("auth", 4),
+    ("syslog", 5),
+    ("lpr", 6),
+    ("news", 7),
+    ("uucp", 8),
+    ("cron", 9),
+    ("authpriv", 10),
+    ("ftp", 11),
+    ("local0", 16),
+    ("local1", 17),
+    ("local2", 18),
+    ("local3", 19),
+    ("local4", 20),
+    ("local5", 21),
+    ("local6", 22),
+    ("local7", 23),
+];
+
+/// facility code by its name
+pub fn facility_code(name: &str) -> Option<u8> {
+    FACILITIES
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|(_, code)| *code)
+}
+
+/// Writer sends each line of child output to syslog or journald
+#[derive(Debug)]
+pub struct JournalWriter {
+    target: OutputTarget,
+    socket_path: String,
+    socket: UnixDatagram,
+
+    facility: u8,
+    severity: u8,
+
+    /// syslog identifier, default is child name
+    tag: String,
+    child_name: String,
+    pid: u32,
+}
+
+impl JournalWriter {
+    /// connect syslog or journald depend on output target
+    pub fn new(output: &Output, name: &str, stream: Stream, pid: u32) -> Result<Self> {
+        let socket_path = match output.target {
+            OutputTarget::Syslog => SYSLOG_SOCKET,
+            OutputTarget::Journald => JOURNALD_SOCKET,
+            OutputTarget::File => {
+                return Err(ioError::new(
+                    ErrorKind::InvalidInput,
+                    "output target is file, not syslog or journald",
+                ))
+            }
+        };
+
+        Self::connect(socket_path, output, name, stream, pid)
+    }
+
+    fn connect(
+        socket_path: &str,
+        output: &Output,
+        name: &str,
+        stream: Stream,
+        pid: u32,
+    ) -> Result<Self> {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(socket_path).map_err(|e| {
+            ioError::new(
+                e.kind(),
+                format!("cannot connect {} of {}: {}", socket_path, output.target, e),
+            )
+        })?;
+
+        Ok(JournalWriter {
+            target: output.target,
+            socket_path: socket_path.to_string(),
+            socket,
+            facility: facility_code(&output.facility).unwrap_or(1),
+            severity: match stream {
+                Stream::Stdout => LOG_INFO,
+                Stream::Stderr => LOG_ERR,
+            },
+            tag: output.tag.clone().unwrap_or_else(|| name.to_string()),
+            child_name: name.to_string(),
+            pid,
+        })
+    }
+
+    /// syslog or journald
+    pub fn target(&self) -> OutputTarget {
+        self.target
+    }
+
+    /// send one line, reconnect once if syslog/journald restarted
+    pub fn send(&mut self, line: &[u8]) -> Result<()> {
+        let msg = self.message(line);
+        if self.socket.send(&msg).is_ok() {
+            return Ok(());
+        }
+
+        self.socket = UnixDatagram::unbound()?;
+        self.socket.connect(&self.socket_path)?;
+        self.socket.send(&msg).map(|_| ())
+    }
+
+    fn message(&self, line: &[u8]) -> Vec<u8> {
+        let line = line.strip_suffix(b"\n").unwrap_or(line);
+
+        match self.target {
+            // <PRI>Mmm dd hh:mm:ss TAG[PID]: MSG
+            OutputTarget::Syslog => {
+                let mut msg = format!(
+                    "<{}>{} {}[{}]: ",
+                    self.facility as u16 * 8 + self.severity as u16,
+                    Local::now().format("%b %e %H:%M:%S"),
+                    self.tag,
+                    self.pid
+                )
+                .into_bytes();
+                msg.extend_from_slice(line);
+                msg
+            }
+            // KEY=value lines of journald native protocol
+            _ => {
+                let mut msg = format!(
+                    "PRIORITY={}\nSYSLOG_FACILITY={}\nSYSLOG_IDENTIFIER={}\nSYSLOG_PID={}\nCHILD_NAME={}\nMESSAGE=",
+                    self.severity, self.facility, self.tag, self.pid, self.child_name
+                )
+                .into_bytes();
+                msg.extend_from_slice(line);
+                msg.push(b'\n');
+                msg
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::test_util::temp_path;
+    use super::*;
+
+    #[test]
+    fn test_send_to_syslog_and_journald() {
+        let path = temp_path("journal");
+        let server = UnixDatagram::bind(&path).unwrap();
+        let mut buf = [0; 1024];
+
+        let mut output = Output::new_empty();
+        output.target = OutputTarget::Syslog;
+        output.facility = "local0".to_string();
+        let mut w = JournalWriter::connect(
+            path.to_str().unwrap(),
+            &output,
+            "child1",
+            Stream::Stderr,
+            42,
+        )
+        .unwrap();
+        w.send(b"hello\n").unwrap();
+
+        let n = server.recv(&mut buf).unwrap();
+        let msg = String::from_utf8_lossy(&buf[..n]).to_string();
+        assert!(msg.starts_with("<131>"));
+        assert!(msg.ends_with(" child1[42]: hello"));
+
+        output.target = OutputTarget::Journald;
+        output.tag = Some("app".to_string());
+        let mut w = JournalWriter::connect(
+            path.to_str().unwrap(),
+            &output,
+            "child1",
+            Stream::Stdout,
+            42,
+        )
+        .unwrap();
+        w.send(b"world\n").unwrap();
+
+        let n = server.recv(&mut buf).unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&buf[..n]),
+            "PRIORITY=6\nSYSLOG_FACILITY=16\nSYSLOG_IDENTIFIER=app\nSYSLOG_PID=42\nCHILD_NAME=child1\nMESSAGE=world\n"
+        );
+    }
+
+    #[test]
+    fn test_facility_code() {
+        assert_eq!(facility_code("daemon"), Some(3));
+        assert_eq!(facility_code("local7"), Some(23));
+        assert_eq!(facility_code("nope"), None);
+    }
+}
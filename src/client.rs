@@ -1,11 +1,19 @@
-use super::keys_handler::DataWrapper;
+use super::keys_handler::{DataWrapper, ReplyKey};
+use super::protocol::{self, Channel};
+use super::tls::TlsOptions;
+use openssl::ssl::SslStream;
 use ssh2::Session;
+use std::fmt;
 use std::io::prelude::*;
-use std::io::{Error, ErrorKind, Result};
-use std::net::{IpAddr, SocketAddr, TcpStream};
+use std::io::{BufReader, Error, ErrorKind, Result};
+use std::net::{IpAddr, SocketAddr, TcpStream, ToSocketAddrs};
+use std::os::unix::net::UnixStream;
 use std::str;
 use std::time::Duration;
 
+/// port server listens on when `listener_port` isn't given
+pub const DEFAULT_PORT: u16 = 33889;
+
 pub const CANNOT_REACH_SERVER_ERROR: &'static str =
     "Looks like client cannot reach server side, make sure you start supervisor-rs-server on host you want to reach. \
 Maybe it is network problem, or even worse, server app terminated. \
@@ -22,6 +30,12 @@ If server app terminated, all children were running become zombies. Check them o
 /// + Help,
 /// + Kill,
 /// + Check,
+/// + Reopen,
+/// + Logs,
+/// + History,
+/// + Status,
+/// + List,
+/// + Diff,
 #[derive(Debug, PartialEq, Clone)]
 pub enum Ops {
     Restart,
@@ -34,10 +48,18 @@ pub enum Ops {
 
     Kill,
     Check,
+
+    Reopen,
+    Logs,
+    History,
+    Status,
+    List,
+    Diff,
+    Keys,
 }
 
 impl Ops {
-    fn from_str(s: &str) -> Result<Self> {
+    pub(crate) fn from_str(s: &str) -> Result<Self> {
         match s {
             "Restart" | "restart" => return Ok(Ops::Restart),
             "Start" | "start" => return Ok(Ops::Start),
@@ -46,6 +68,13 @@ impl Ops {
             "Kill" | "kill" => return Ok(Ops::Kill),
             "TryStart" | "Trystart" | "trystart" => return Ok(Ops::TryStart),
             "Info" | "INFO" | "InFo" | "info" => Ok(Ops::Info),
+            "Reopen" | "reopen" => Ok(Ops::Reopen),
+            "Logs" | "logs" | "Tail" | "tail" => Ok(Ops::Logs),
+            "History" | "history" => Ok(Ops::History),
+            "Status" | "status" => Ok(Ops::Status),
+            "List" | "list" => Ok(Ops::List),
+            "Diff" | "diff" => Ok(Ops::Diff),
+            "Keys" | "keys" => Ok(Ops::Keys),
             "Help" | "help" | "-h" => return Ok(Ops::Help),
             _ => {
                 return Err(Error::new(
@@ -66,6 +95,13 @@ impl Ops {
             Ops::TryStart => return "trystart".to_string(),
             Ops::Help => return "help".to_string(),
             Ops::Info => return "info".to_string(),
+            Ops::Reopen => "reopen".to_string(),
+            Ops::Logs => "logs".to_string(),
+            Ops::History => "history".to_string(),
+            Ops::Status => "status".to_string(),
+            Ops::List => "list".to_string(),
+            Ops::Diff => "diff".to_string(),
+            Ops::Keys => "keys".to_string(),
         }
     }
 
@@ -124,6 +160,138 @@ impl Prepositions {
     }
 }
 
+/// Format of server response
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
+impl OutputFormat {
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("does not support output {}, only text or json", s),
+            )),
+        }
+    }
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OutputFormat::Text => write!(f, "text"),
+            OutputFormat::Json => write!(f, "json"),
+        }
+    }
+}
+
+/// Flags of command, start with "--"
+///
+/// + `--lines N`
+/// + `--stderr`
+/// + `--follow`
+/// + `--output text|json`
+#[derive(Debug, PartialEq, Clone)]
+pub enum Flags {
+    Lines(usize),
+    Stderr,
+    Follow,
+    Output(OutputFormat),
+
+    /// connect to all addresses of hostname, not only the first one.
+    /// client side only, not sent to server
+    AllAddrs,
+
+    /// TLS connection, and its CA, client certificate and key.
+    /// client side only, not sent to server
+    Tls,
+    Ca(String),
+    Cert(String),
+    Key(String),
+
+    /// client runs inside ssh session, added by client itself
+    Ssh,
+}
+
+impl Flags {
+    /// parse flag from the head of s, return flag and how many words it takes
+    fn parse(s: &[&str]) -> Result<(Self, usize)> {
+        match s[0] {
+            "--lines" | "-n" => {
+                let n = s
+                    .get(1)
+                    .and_then(|n| n.parse::<usize>().ok())
+                    .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "--lines need a number"))?;
+                Ok((Flags::Lines(n), 2))
+            }
+            "--output" | "-o" => {
+                let format = s.get(1).ok_or_else(|| {
+                    Error::new(ErrorKind::InvalidInput, "--output need text or json")
+                })?;
+                Ok((Flags::Output(OutputFormat::from_str(format)?), 2))
+            }
+            "--stderr" => Ok((Flags::Stderr, 1)),
+            "--follow" | "-f" => Ok((Flags::Follow, 1)),
+            "--ssh" => Ok((Flags::Ssh, 1)),
+            "--all-addrs" => Ok((Flags::AllAddrs, 1)),
+            "--tls" => Ok((Flags::Tls, 1)),
+            "--ca" | "--cert" | "--key" => {
+                let path = s.get(1).ok_or_else(|| {
+                    Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("{} need a file path", s[0]),
+                    )
+                })?;
+                Ok((
+                    match s[0] {
+                        "--ca" => Flags::Ca(path.to_string()),
+                        "--cert" => Flags::Cert(path.to_string()),
+                        _ => Flags::Key(path.to_string()),
+                    },
+                    2,
+                ))
+            }
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("does not support flag {}", s[0]),
+            )),
+        }
+    }
+
+    /// flags only used by client itself
+    fn is_client_only(&self) -> bool {
+        matches!(
+            self,
+            Flags::AllAddrs | Flags::Tls | Flags::Ca(_) | Flags::Cert(_) | Flags::Key(_)
+        )
+    }
+
+    fn is_flag(s: &str) -> bool {
+        s.starts_with("--") || s == "-n" || s == "-f" || s == "-o"
+    }
+}
+
+impl fmt::Display for Flags {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Flags::Lines(n) => write!(f, "--lines {}", n),
+            Flags::Stderr => write!(f, "--stderr"),
+            Flags::Follow => write!(f, "--follow"),
+            Flags::Output(format) => write!(f, "--output {}", format),
+            Flags::AllAddrs => write!(f, "--all-addrs"),
+            Flags::Tls => write!(f, "--tls"),
+            Flags::Ca(path) => write!(f, "--ca {}", path),
+            Flags::Cert(path) => write!(f, "--cert {}", path),
+            Flags::Key(path) => write!(f, "--key {}", path),
+            Flags::Ssh => write!(f, "--ssh"),
+        }
+    }
+}
+
 /// Command struct of client using to talk to server side
 #[derive(Debug, PartialEq)]
 pub struct Command {
@@ -131,6 +299,7 @@ pub struct Command {
     pub child_name: Option<String>,
     pub prep: Option<Vec<Prepositions>>,
     pub obj: Option<Vec<String>>,
+    pub flags: Vec<Flags>,
 }
 
 impl Command {
@@ -140,6 +309,7 @@ impl Command {
             child_name: None,
             prep: None,
             obj: None,
+            flags: vec![],
         }
     }
 
@@ -152,10 +322,18 @@ impl Command {
         // get op
         let mut re = Self::new(Ops::from_str(s[0])?);
 
-        // kill and check do not have to have child name
-        if re.op == Ops::Kill || re.op == Ops::Check || re.op == Ops::Info {
+        // kill, check, info, reopen, status and list do not have to have child name.
+        // keys has no child name, day_care refuses it
+        if re.op == Ops::Kill
+            || re.op == Ops::Keys
+            || re.op == Ops::Check
+            || re.op == Ops::Info
+            || re.op == Ops::Reopen
+            || re.op == Ops::Status
+            || re.op == Ops::List
+        {
             s.drain(..1); // delete ops
-            if s.len() >= 1 && !Prepositions::is_prep(s[0]) {
+            if s.len() >= 1 && !Prepositions::is_prep(s[0]) && !Flags::is_flag(s[0]) {
                 // has child name
                 if Ops::is_op(s[0]) {
                     // check child name
@@ -171,6 +349,12 @@ impl Command {
         } else {
             // other commands
             s.drain(..1); // delete ops
+            if s.is_empty() || Flags::is_flag(s[0]) {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("{} needs child name", re.op.to_string()),
+                ));
+            }
             if Ops::is_op(s[0]) {
                 // check child name
                 return Err(Error::new(
@@ -184,23 +368,30 @@ impl Command {
         }
 
         // parse all else
-        if s.len() % 2 != 0 {
-            return Err(Error::new(
-                ErrorKind::InvalidInput,
-                "prep & obj arguments number should be even",
-            ));
-        }
-
         let mut i = 0;
         let mut prep_cache = vec![];
         let mut obj_cache = vec![];
         while i < s.len() {
+            if Flags::is_flag(s[i]) {
+                let (flag, step) = Flags::parse(&s[i..])?;
+                re.flags.push(flag);
+                i += step;
+                continue;
+            }
+
+            if i + 1 >= s.len() {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "prep & obj arguments number should be even",
+                ));
+            }
+
             prep_cache.push(Prepositions::from_str(s[i])?);
             obj_cache.push(s[i + 1].to_string());
             i += 2;
         }
 
-        if i != 0 {
+        if !prep_cache.is_empty() {
             re.prep = Some(prep_cache);
             re.obj = Some(obj_cache);
         }
@@ -212,6 +403,92 @@ impl Command {
         self.op.clone()
     }
 
+    pub fn has_flag(&self, flag: &Flags) -> bool {
+        self.flags.contains(flag)
+    }
+
+    /// value of "--lines" flag
+    pub fn lines(&self) -> Option<usize> {
+        self.flags.iter().find_map(|f| match f {
+            Flags::Lines(n) => Some(*n),
+            _ => None,
+        })
+    }
+
+    /// value of "--output" flag, text by default
+    pub fn output_format(&self) -> OutputFormat {
+        self.flags
+            .iter()
+            .find_map(|f| match f {
+                Flags::Output(format) => Some(*format),
+                _ => None,
+            })
+            .unwrap_or(OutputFormat::Text)
+    }
+
+    /// TLS options if any of --tls, --ca, --cert and --key is given
+    pub fn tls_options(&self) -> Option<TlsOptions> {
+        let mut opts = None;
+        for flag in &self.flags {
+            let o: &mut TlsOptions = match flag {
+                Flags::Tls | Flags::Ca(_) | Flags::Cert(_) | Flags::Key(_) => {
+                    opts.get_or_insert_with(TlsOptions::default)
+                }
+                _ => continue,
+            };
+            match flag {
+                Flags::Ca(path) => o.ca = Some(path.clone()),
+                Flags::Cert(path) => o.cert = Some(path.clone()),
+                Flags::Key(path) => o.key = Some(path.clone()),
+                _ => {}
+            }
+        }
+        opts
+    }
+
+    /// parse json request like {"op": "logs", "child": "child1", "flags": ["--lines", "50"]},
+    /// response of json request is json too
+    pub fn new_from_json(s: &str) -> Result<Self> {
+        let request: serde_json::Value = serde_json::from_str(s)
+            .map_err(|e| Error::new(ErrorKind::InvalidInput, e.to_string()))?;
+
+        let field = |name: &str| match &request[name] {
+            serde_json::Value::Null => Ok(None),
+            serde_json::Value::String(v) => Ok(Some(v.as_str())),
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("{} of json request should be string", name),
+            )),
+        };
+
+        let mut words = vec![field("op")?
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "json request needs op"))?];
+        if let Some(child) = field("child")? {
+            words.push(child);
+        }
+        if let Some(flags) = request["flags"].as_array() {
+            for f in flags {
+                words.push(f.as_str().ok_or_else(|| {
+                    Error::new(
+                        ErrorKind::InvalidInput,
+                        "flags of json request should be strings",
+                    )
+                })?);
+            }
+        }
+
+        let mut re = Self::new_from_str(words)?;
+        if re.prep.is_some() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "json request cannot have prepositions",
+            ));
+        }
+        re.flags.retain(|f| !matches!(f, Flags::Output(_)));
+        re.flags.push(Flags::Output(OutputFormat::Json));
+        Ok(re)
+    }
+
     pub fn prep_obj_pairs(&self) -> Option<Vec<(&Prepositions, &String)>> {
         if self.prep.is_none()
             || self.prep.as_ref().unwrap().len() != self.obj.as_ref().unwrap().len()
@@ -263,7 +540,7 @@ impl Command {
         }
     }
 
-    /// ops + ' ' + childname
+    /// ops + ' ' + childname + ' ' + flags
     /// and there are no Prepositions and Objects inside
     pub fn as_bytes(&self) -> Vec<u8> {
         let mut cache = self.op.to_string().as_bytes().to_vec();
@@ -279,17 +556,46 @@ impl Command {
             );
         }
 
+        for flag in self.flags.iter().filter(|f| !f.is_client_only()) {
+            cache.push(b' ');
+            cache.append(&mut flag.to_string().into_bytes());
+        }
+
         cache.clone()
     }
 }
 
 #[derive(Debug, PartialEq)]
 pub enum IpFields<'a> {
-    Normal(IpAddr),
-    SshIp { username: &'a str, ipaddr: IpAddr },
+    Normal(SocketAddr),
+    SshIp {
+        username: &'a str,
+        addr: SocketAddr,
+    },
+
+    /// path of server's unix socket
+    Unix(&'a str),
+
+    /// address of hostname, hostname is kept for TLS verification
+    Resolved {
+        host: &'a str,
+        addr: SocketAddr,
+    },
+
+    /// hostname, resolved by resolve_hosts before connecting
+    Host {
+        host: &'a str,
+        port: u16,
+    },
+    SshHost {
+        username: &'a str,
+        host: &'a str,
+        port: u16,
+    },
 }
 
-/// ip address parser, support normal ip address and ssh protocol
+/// ip address parser, support normal ip address, hostname and ssh protocol.
+/// address can have port, like "127.0.0.1:33890", "[::1]:33890" or "web-01:33890"
 pub fn ip_fields_parser<'a>(
     ip_pair: impl Iterator<Item = &'a (&'a Prepositions, &'a String)>,
 ) -> std::result::Result<Vec<IpFields<'a>>, String> {
@@ -301,74 +607,217 @@ pub fn ip_fields_parser<'a>(
     for s in cache {
         if s.starts_with("ssh://") {
             result.push(ssh_address_parse(s)?);
+        } else if let Some(path) = s.strip_prefix("unix://") {
+            // "unix:///run/supervisor-rs.sock"
+            if !path.starts_with('/') {
+                return Err(format!("unix socket path of {} should be absolute", s));
+            }
+            result.push(IpFields::Unix(path));
         } else {
-            result.push(IpFields::Normal(
-                s.parse::<IpAddr>().map_err(|e| e.to_string())?,
-            ))
+            result.push(match host_port_parse(s, DEFAULT_PORT)? {
+                Target::Ip(addr) => IpFields::Normal(addr),
+                Target::Host(host, port) => IpFields::Host { host, port },
+            });
         }
     }
     Ok(result)
 }
 
-/// ssh address has to follow 'ssh://username@ipaddress'
+/// parsed address, hostname isn't resolved yet
+enum Target<'a> {
+    Ip(SocketAddr),
+    Host(&'a str, u16),
+}
+
+/// "host:port", "[v6]:port", or host only with default port.
+/// host is ip address or hostname
+fn host_port_parse(address: &str, default_port: u16) -> std::result::Result<Target<'_>, String> {
+    if let Ok(sock) = address.parse::<SocketAddr>() {
+        return Ok(Target::Ip(sock));
+    }
+    if let Ok(ip) = address.parse::<IpAddr>() {
+        return Ok(Target::Ip(SocketAddr::new(ip, default_port)));
+    }
+
+    let illegal = || format!("{} is not an address or address:port", address);
+    let (host, port) = match address.rsplit_once(':') {
+        Some((host, port)) => (host, port.parse::<u16>().map_err(|_| illegal())?),
+        None => (address, default_port),
+    };
+
+    if host.is_empty()
+        || !host
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.' || c == '_')
+    {
+        return Err(illegal());
+    }
+    Ok(Target::Host(host, port))
+}
+
+/// ssh address has to follow 'ssh://username@host[:port]'
 fn ssh_address_parse(address: &str) -> std::result::Result<IpFields<'_>, String> {
-    let mut ll = address
-        .split(|x| x == '/' || x == ':' || x == '@')
-        .filter(|s| *s != "");
+    let (username, host) = address
+        .strip_prefix("ssh://")
+        .ok_or("Only support ssh protocol".to_string())?
+        .split_once('@')
+        .ok_or("Username parse wrong".to_string())?;
 
-    if ll.nth(0).unwrap_or("") != "ssh" {
-        return Err("Only support ssh protocol".to_string());
+    if username.is_empty() {
+        return Err("Username parse wrong".to_string());
     }
 
-    Ok(IpFields::SshIp {
-        username: ll.nth(0).ok_or("Username parse wrong".to_string())?,
-        ipaddr: ll
-            .nth(0)
-            .ok_or("IP address wrong".to_string())?
-            .parse::<IpAddr>()
-            .map_err(|e| e.to_string())?,
+    Ok(match host_port_parse(host, 22)? {
+        Target::Ip(addr) => IpFields::SshIp { username, addr },
+        Target::Host(host, port) => IpFields::SshHost {
+            username,
+            host,
+            port,
+        },
     })
 }
 
+/// resolve hostnames to ip addresses, keep all records of each host
+/// or only the first one
+pub fn resolve_hosts(
+    fields: Vec<IpFields<'_>>,
+    all: bool,
+) -> std::result::Result<Vec<IpFields<'_>>, String> {
+    let mut result = vec![];
+    for field in fields {
+        match field {
+            IpFields::Host { host, port } => result.extend(
+                lookup_host(host, port, all)?
+                    .into_iter()
+                    .map(|addr| IpFields::Resolved { host, addr }),
+            ),
+            IpFields::SshHost {
+                username,
+                host,
+                port,
+            } => result.extend(
+                lookup_host(host, port, all)?
+                    .into_iter()
+                    .map(|addr| IpFields::SshIp { username, addr }),
+            ),
+            f => result.push(f),
+        }
+    }
+    Ok(result)
+}
+
+fn lookup_host(host: &str, port: u16, all: bool) -> std::result::Result<Vec<SocketAddr>, String> {
+    let mut addrs = (host, port)
+        .to_socket_addrs()
+        .map_err(|e| format!("cannot resolve host {}: {}", host, e))?
+        .collect::<Vec<SocketAddr>>();
+
+    if addrs.is_empty() {
+        return Err(format!("cannot resolve host {}: no address found", host));
+    }
+    if !all {
+        addrs.truncate(1);
+    }
+    Ok(addrs)
+}
+
+/// error of connecting, tells which address cannot be reached
+fn unreachable(addr: &str, e: impl fmt::Display) -> String {
+    format!(
+        "cannot connect to {}: {}. {}",
+        addr, e, CANNOT_REACH_SERVER_ERROR
+    )
+}
+
 pub enum ConnectionStream {
     Tcp(TcpStream),
     Ssh(Session, String),
+    Unix(UnixStream, String),
+    Tls(SslStream<TcpStream>, String),
 }
 
 impl ConnectionStream {
     /// generate new connections by using IpFields
     pub fn new(ip: IpFields<'_>) -> std::result::Result<ConnectionStream, String> {
         match ip {
-            IpFields::Normal(addr) => {
-                let sock = SocketAddr::new(addr, 33889);
-                Ok(Self::Tcp(
-                    TcpStream::connect_timeout(&sock, Duration::new(5, 0))
-                        .map_err(|_| CANNOT_REACH_SERVER_ERROR)?,
-                ))
-            }
-            IpFields::SshIp { username, ipaddr } => {
-                let sock = SocketAddr::new(ipaddr, 22);
-                let tcp = TcpStream::connect_timeout(&sock, Duration::new(5, 0))
-                    .map_err(|_| CANNOT_REACH_SERVER_ERROR)?;
+            IpFields::Normal(sock) | IpFields::Resolved { addr: sock, .. } => Ok(Self::Tcp(
+                TcpStream::connect_timeout(&sock, Duration::new(5, 0))
+                    .map_err(|e| unreachable(&sock.to_string(), e))?,
+            )),
+            IpFields::SshIp { username, addr } => {
+                let tcp = TcpStream::connect_timeout(&addr, Duration::new(5, 0))
+                    .map_err(|e| unreachable(&addr.to_string(), e))?;
                 let mut sess = Session::new().unwrap();
                 sess.set_tcp_stream(tcp);
                 sess.handshake().map_err(|e| e.to_string())?;
                 sess.userauth_agent(username).map_err(|e| e.to_string())?;
-                Ok(Self::Ssh(sess, ipaddr.to_string()))
+                Ok(Self::Ssh(
+                    sess,
+                    // keep old display when ssh port is default
+                    if addr.port() == 22 {
+                        addr.ip().to_string()
+                    } else {
+                        addr.to_string()
+                    },
+                ))
             }
+            IpFields::Host { host, .. } | IpFields::SshHost { host, .. } => {
+                Err(format!("host {} has to be resolved first", host))
+            }
+            IpFields::Unix(path) => Ok(Self::Unix(
+                UnixStream::connect(path).map_err(|e| unreachable(path, e))?,
+                path.to_string(),
+            )),
         }
     }
 
-    /// send command to server during the built streams
-    pub fn send_comm(&mut self, comm: &[u8]) -> Result<String> {
+    /// TLS connection, server certificate is verified against hostname or ip address
+    pub fn new_tls(ip: IpFields<'_>, opts: &TlsOptions) -> std::result::Result<Self, String> {
+        let (domain, sock) = match ip {
+            IpFields::Normal(sock) => (sock.ip().to_string(), sock),
+            IpFields::Resolved { host, addr } => (host.to_string(), addr),
+            _ => return Err("tls only works with tcp address".to_string()),
+        };
+
+        let tcp = TcpStream::connect_timeout(&sock, Duration::new(5, 0))
+            .map_err(|e| unreachable(&sock.to_string(), e))?;
+        Ok(Self::Tls(
+            opts.connect(&domain, tcp).map_err(|e| e.to_string())?,
+            sock.to_string(),
+        ))
+    }
+
+    /// send command to server during the built streams.
+    /// reply key decrypts response of signed command
+    pub fn send_comm(&mut self, comm: &[u8], reply: Option<ReplyKey>) -> Result<String> {
         match self {
             ConnectionStream::Tcp(s) => {
-                s.write_all(comm)?;
+                let mut ch = open_channel(s)?;
+                ch.send(comm)?;
+                expect_sealed(&mut ch, reply);
+
+                let mut response = String::new();
+                ch.read_to_string(&mut response)?;
+
+                Ok(response)
+            }
+            ConnectionStream::Unix(s, _) => {
+                let mut ch = open_framed_channel(s)?;
+                ch.send(comm)?;
+                expect_sealed(&mut ch, reply);
 
-                s.flush()?;
+                let mut response = String::new();
+                ch.read_to_string(&mut response)?;
+
+                Ok(response)
+            }
+            ConnectionStream::Tls(s, _) => {
+                let mut ch = open_framed_channel(s)?;
+                ch.send(comm)?;
+                expect_sealed(&mut ch, reply);
 
                 let mut response = String::new();
-                s.read_to_string(&mut response)?;
+                ch.read_to_string(&mut response)?;
 
                 Ok(response)
             }
@@ -387,14 +836,96 @@ impl ConnectionStream {
         }
     }
 
+    /// send command and keep reading response line by line until connection closed
+    pub fn follow_comm(
+        &mut self,
+        comm: &[u8],
+        reply: Option<ReplyKey>,
+        mut f: impl FnMut(&str),
+    ) -> Result<()> {
+        match self {
+            ConnectionStream::Tcp(s) => {
+                let mut ch = open_channel(s)?;
+                ch.send(comm)?;
+                expect_sealed(&mut ch, reply);
+                read_lines(BufReader::new(ch), &mut f)
+            }
+            ConnectionStream::Unix(s, _) => {
+                let mut ch = open_framed_channel(s)?;
+                ch.send(comm)?;
+                expect_sealed(&mut ch, reply);
+                read_lines(BufReader::new(ch), &mut f)
+            }
+            ConnectionStream::Tls(s, _) => {
+                let mut ch = open_framed_channel(s)?;
+                ch.send(comm)?;
+                expect_sealed(&mut ch, reply);
+                read_lines(BufReader::new(ch), &mut f)
+            }
+            ConnectionStream::Ssh(s, _) => {
+                let mut channel = s.channel_session()?;
+                let mut head = "supervisor-rs-client ".to_string();
+                head.push_str(str::from_utf8(comm).unwrap());
+                channel.exec(head.as_str())?;
+
+                read_lines(BufReader::new(&mut channel), &mut f)?;
+                channel.wait_close()?;
+                Ok(())
+            }
+        }
+    }
+
     pub fn address(&self) -> std::result::Result<String, String> {
         Ok(match self {
             ConnectionStream::Tcp(s) => s.peer_addr().map_err(|e| e.to_string())?.to_string(),
             ConnectionStream::Ssh(_, addr) => addr.clone(),
+            ConnectionStream::Unix(_, path) => format!("unix://{}", path),
+            ConnectionStream::Tls(_, addr) => addr.clone(),
         })
     }
 }
 
+/// handshake with server, reconnect and use legacy protocol if server is too old
+fn open_channel(s: &mut TcpStream) -> Result<Channel<&mut TcpStream>> {
+    if let Some(version) = protocol::handshake(s)? {
+        return Ok(Channel::framed(s, version));
+    }
+
+    // old server has taken hello frame as a command, so connect again
+    *s = TcpStream::connect_timeout(&s.peer_addr()?, Duration::new(5, 0))?;
+    Ok(Channel::legacy(s))
+}
+
+/// unix socket and TLS only speak framed protocol
+fn open_framed_channel<S: Read + Write>(s: &mut S) -> Result<Channel<&mut S>> {
+    match protocol::handshake(s)? {
+        Some(version) => Ok(Channel::framed(s, version)),
+        None => Err(Error::new(
+            ErrorKind::InvalidData,
+            "server does not answer handshake",
+        )),
+    }
+}
+
+/// response of server is encrypted to reply key of signed command
+fn expect_sealed<S: Read + Write>(ch: &mut Channel<S>, reply: Option<ReplyKey>) {
+    if let Some(r) = reply {
+        ch.set_cipher(Box::new(r));
+    }
+}
+
+/// call f on each line from reader
+fn read_lines(mut reader: impl BufRead, f: &mut impl FnMut(&str)) -> Result<()> {
+    let mut line = vec![];
+    loop {
+        line.clear();
+        if reader.read_until(b'\n', &mut line)? == 0 {
+            return Ok(());
+        }
+        f(String::from_utf8_lossy(&line).trim_end_matches('\n'));
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -425,6 +956,7 @@ mod tests {
                     "host".to_string(),
                     "host1".to_string()
                 ]),
+                flags: vec![],
             },
             Command::new_from_str(case0).unwrap()
         );
@@ -437,11 +969,92 @@ mod tests {
                 child_name: Some("child".to_string()),
                 prep: Some(vec![Prepositions::With, Prepositions::On]),
                 obj: Some(vec!["key".to_string(), "host1, host2".to_string(),]),
+                flags: vec![],
             },
             Command::new_from_str(case1).unwrap()
         );
     }
 
+    #[test]
+    fn check_parse_flags() {
+        let case0 = vec![
+            "logs", "child", "--lines", "200", "--stderr", "on", "host", "-f",
+        ];
+        let comm = Command::new_from_str(case0).unwrap();
+        assert_eq!(
+            comm.flags,
+            vec![Flags::Lines(200), Flags::Stderr, Flags::Follow]
+        );
+        assert_eq!(comm.lines(), Some(200));
+        assert!(comm.has_flag(&Flags::Stderr));
+        assert_eq!(comm.obj, Some(vec!["host".to_string()]));
+        assert_eq!(
+            comm.as_bytes(),
+            b"logs child --lines 200 --stderr --follow".to_vec()
+        );
+
+        assert!(Command::new_from_str(vec!["logs", "child", "--lines"]).is_err());
+        assert!(Command::new_from_str(vec!["logs", "child", "--what"]).is_err());
+        assert!(Command::new_from_str(vec!["logs", "child", "on"]).is_err());
+
+        let comm = Command::new_from_str(vec!["kill", "--ssh"]).unwrap();
+        assert_eq!(comm.child_name, None);
+        assert!(comm.has_flag(&Flags::Ssh));
+
+        let comm = Command::new_from_str(vec!["status", "-o", "json"]).unwrap();
+        assert_eq!(comm.output_format(), OutputFormat::Json);
+        assert_eq!(comm.as_bytes(), b"status --output json".to_vec());
+        assert!(Command::new_from_str(vec!["status", "--output", "yaml"]).is_err());
+
+        // client side only flag
+        let comm =
+            Command::new_from_str(vec!["status", "--all-addrs", "on", "web.internal"]).unwrap();
+        assert!(comm.has_flag(&Flags::AllAddrs));
+        assert_eq!(comm.as_bytes(), b"status".to_vec());
+        assert_eq!(comm.tls_options(), None);
+
+        let comm = Command::new_from_str(vec![
+            "restart",
+            "child1",
+            "--cert",
+            "me.crt",
+            "--ca",
+            "ca.crt",
+            "on",
+            "web.internal",
+        ])
+        .unwrap();
+        assert_eq!(
+            comm.tls_options(),
+            Some(TlsOptions {
+                ca: Some("ca.crt".to_string()),
+                cert: Some("me.crt".to_string()),
+                key: None,
+            })
+        );
+        assert_eq!(comm.as_bytes(), b"restart child1".to_vec());
+        assert!(Command::new_from_str(vec!["status", "--tls", "--ca"]).is_err());
+        assert!(Command::new_from_str(vec!["restart"]).is_err());
+    }
+
+    #[test]
+    fn check_parse_json_request() {
+        let comm =
+            Command::new_from_json(r#"{"op": "logs", "child": "child1", "flags": ["-n", "5"]}"#)
+                .unwrap();
+        assert_eq!(comm.get_ops(), Ops::Logs);
+        assert_eq!(comm.child_name, Some("child1".to_string()));
+        assert_eq!(comm.lines(), Some(5));
+        assert_eq!(comm.output_format(), OutputFormat::Json);
+
+        let comm = Command::new_from_json(r#"{"op": "status"}"#).unwrap();
+        assert_eq!(comm.child_name, None);
+
+        assert!(Command::new_from_json(r#"{"child": "child1"}"#).is_err());
+        assert!(Command::new_from_json(r#"{"op": "start"}"#).is_err());
+        assert!(Command::new_from_json(r#"{"op": "start", "child": 1}"#).is_err());
+    }
+
     #[test]
     fn check_make_pairs() {
         let case0 = Command {
@@ -449,6 +1062,7 @@ mod tests {
             child_name: Some("child".to_string()),
             prep: None,
             obj: None,
+            flags: vec![],
         };
         assert_eq!(case0.prep_obj_pairs(), None);
     }
@@ -498,14 +1112,14 @@ mod tests {
 
         assert_eq!(
             ip_fields_parser(vec![test0[0]].iter()),
-            Ok(vec![IpFields::Normal("127.0.0.1".parse().unwrap())]),
+            Ok(vec![IpFields::Normal("127.0.0.1:33889".parse().unwrap())]),
         );
 
         assert_eq!(
             ip_fields_parser(vec![test0[1]].iter()),
             Ok(vec![
-                IpFields::Normal("127.0.0.2".parse().unwrap()),
-                IpFields::Normal("127.0.0.3".parse().unwrap())
+                IpFields::Normal("127.0.0.2:33889".parse().unwrap()),
+                IpFields::Normal("127.0.0.3:33889".parse().unwrap())
             ]),
         );
 
@@ -513,7 +1127,7 @@ mod tests {
             ip_fields_parser(vec![test0[2]].iter()),
             Ok(vec![IpFields::SshIp {
                 username: "hello",
-                ipaddr: "127.0.0.1".parse().unwrap()
+                addr: "127.0.0.1:22".parse().unwrap()
             }]),
         );
         assert_eq!(
@@ -521,14 +1135,110 @@ mod tests {
             Ok(vec![
                 IpFields::SshIp {
                     username: "hello",
-                    ipaddr: "127.0.0.1".parse().unwrap()
+                    addr: "127.0.0.1:22".parse().unwrap()
                 },
                 IpFields::SshIp {
                     username: "hello",
-                    ipaddr: "127.0.0.2".parse().unwrap()
+                    addr: "127.0.0.2:22".parse().unwrap()
                 }
             ]),
         );
         assert!(ip_fields_parser(vec![test0[4]].iter()).is_err());
+
+        let bad_port = (Prepositions::On, "127.0.0.1:port".to_string());
+        assert_eq!(
+            ip_fields_parser([(&bad_port.0, &bad_port.1)].iter()),
+            Err("127.0.0.1:port is not an address or address:port".to_string()),
+        );
+        let ports = (
+            Prepositions::On,
+            "127.0.0.1:33890, [::1]:33891, ::1".to_string(),
+        );
+        assert_eq!(
+            ip_fields_parser([(&ports.0, &ports.1)].iter()),
+            Ok(vec![
+                IpFields::Normal("127.0.0.1:33890".parse().unwrap()),
+                IpFields::Normal("[::1]:33891".parse().unwrap()),
+                IpFields::Normal("[::1]:33889".parse().unwrap()),
+            ]),
+        );
+
+        let unix = (
+            Prepositions::On,
+            "unix:///run/supervisor-rs.sock".to_string(),
+        );
+        assert_eq!(
+            ip_fields_parser([(&unix.0, &unix.1)].iter()),
+            Ok(vec![IpFields::Unix("/run/supervisor-rs.sock")]),
+        );
+        let unix = (
+            Prepositions::On,
+            "unix://run/supervisor-rs.sock".to_string(),
+        );
+        assert!(ip_fields_parser([(&unix.0, &unix.1)].iter()).is_err());
+
+        let hosts = (
+            Prepositions::On,
+            "web-01.internal, localhost:33890, ssh://hello@web-01:2222, ssh://hello@[::1]:2222"
+                .to_string(),
+        );
+        assert_eq!(
+            ip_fields_parser([(&hosts.0, &hosts.1)].iter()),
+            Ok(vec![
+                IpFields::Host {
+                    host: "web-01.internal",
+                    port: 33889
+                },
+                IpFields::Host {
+                    host: "localhost",
+                    port: 33890
+                },
+                IpFields::SshHost {
+                    username: "hello",
+                    host: "web-01",
+                    port: 2222
+                },
+                IpFields::SshIp {
+                    username: "hello",
+                    addr: "[::1]:2222".parse().unwrap()
+                },
+            ]),
+        );
+        let bad_host = (Prepositions::On, "web/01".to_string());
+        assert!(ip_fields_parser([(&bad_host.0, &bad_host.1)].iter()).is_err());
+    }
+
+    #[test]
+    fn test_resolve_hosts() {
+        let resolved = resolve_hosts(
+            vec![
+                IpFields::Host {
+                    host: "localhost",
+                    port: 33890,
+                },
+                IpFields::Normal("127.0.0.2:33889".parse().unwrap()),
+            ],
+            false,
+        )
+        .unwrap();
+        assert_eq!(resolved.len(), 2);
+        match &resolved[0] {
+            IpFields::Resolved { host, addr } => {
+                assert_eq!(*host, "localhost");
+                assert!(addr.ip().is_loopback());
+                assert_eq!(addr.port(), 33890);
+            }
+            f => panic!("{:?} is not resolved", f),
+        }
+
+        let e = resolve_hosts(
+            vec![IpFields::Host {
+                host: "no-such-host.invalid",
+                port: 33889,
+            }],
+            true,
+        )
+        .unwrap_err();
+        assert!(e.starts_with("cannot resolve host no-such-host.invalid"));
     }
 }
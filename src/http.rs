@@ -0,0 +1,268 @@
+//! Minimal HTTP/1.1 REST API of server. Every request is mapped to one
+//! command of client, and answered with json response of that command.
+//!
+//! + `GET /children`
+//! + `GET /children/{name}`
+//! + `POST /children/{name}/start|stop|restart|trystart`
+//! + `GET /info`
+//! + `POST /shutdown`
+//!
+//! in encrypt mode, GET apis are refused, responses are not encrypted
+use super::acl;
+use openssl::base64;
+use std::io::{BufRead, Error as ioError, ErrorKind, Read, Result, Write};
+
+/// biggest request head accepted
+const MAX_HEAD_SIZE: usize = 8 * 1024;
+
+/// biggest request body accepted, body is not used so far
+const MAX_BODY_SIZE: usize = 64 * 1024;
+
+/// scheme of Authorization header in encrypt mode
+const AUTH_SCHEME: &str = "Supervisor";
+
+/// HTTP request, only parts API uses
+#[derive(Debug, PartialEq)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    headers: Vec<(String, String)>,
+}
+
+impl Request {
+    /// read request line, headers and drop body
+    pub fn read_from<R: BufRead>(r: &mut R) -> Result<Self> {
+        let mut head_size = 0;
+        let mut lines = vec![];
+        loop {
+            let mut line = String::new();
+            let n = r.by_ref().take(MAX_HEAD_SIZE as u64).read_line(&mut line)?;
+            head_size += n;
+            if n == 0 || head_size > MAX_HEAD_SIZE {
+                return Err(ioError::new(
+                    ErrorKind::InvalidData,
+                    "request head is broken or too big",
+                ));
+            }
+
+            let line = line.trim_end().to_string();
+            if line.is_empty() {
+                break;
+            }
+            lines.push(line);
+        }
+
+        if lines.is_empty() {
+            return Err(ioError::new(
+                ErrorKind::InvalidData,
+                "request line is empty",
+            ));
+        }
+        let request_line = lines.remove(0);
+        let mut parts = request_line.split(' ');
+        let (method, target) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(m), Some(t), Some(v)) if v.starts_with("HTTP/1.") => (m, t),
+            _ => {
+                return Err(ioError::new(
+                    ErrorKind::InvalidData,
+                    format!("bad request line: {}", request_line),
+                ))
+            }
+        };
+
+        let headers = lines
+            .iter()
+            .filter_map(|l| l.split_once(':'))
+            .map(|(k, v)| (k.trim().to_lowercase(), v.trim().to_string()))
+            .collect::<Vec<_>>();
+
+        let request = Request {
+            method: method.to_string(),
+            // query string is not used
+            path: target.split('?').next().unwrap_or("").to_string(),
+            headers,
+        };
+
+        let body_size = request
+            .header("content-length")
+            .map_or(Ok(0), |l| l.parse::<usize>())
+            .map_err(|e| ioError::new(ErrorKind::InvalidData, e.to_string()))?;
+        if body_size > MAX_BODY_SIZE {
+            return Err(ioError::new(
+                ErrorKind::InvalidData,
+                "request body is too big",
+            ));
+        }
+        r.read_exact(&mut vec![0; body_size])?;
+
+        Ok(request)
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k == &name.to_lowercase())
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// "Authorization: Supervisor <base64 of keyname;encrypted command>"
+    pub fn auth_token(&self) -> Result<Vec<u8>> {
+        let token = self
+            .header("authorization")
+            .and_then(|v| v.strip_prefix(AUTH_SCHEME))
+            .map(|t| t.trim())
+            .ok_or_else(|| {
+                ioError::new(
+                    ErrorKind::PermissionDenied,
+                    format!("encrypt mode needs Authorization: {} header", AUTH_SCHEME),
+                )
+            })?;
+
+        base64::decode_block(token)
+            .map_err(|e| ioError::new(ErrorKind::PermissionDenied, e.to_string()))
+    }
+
+    /// response is not encrypted, encrypt mode refuses GET apis which read children and keys
+    pub fn refuse_read(&self) -> Result<()> {
+        if self.method == "GET" {
+            return Err(ioError::new(
+                ErrorKind::Unsupported,
+                format!(
+                    "GET {} is refused in encrypt mode, HTTP response is not encrypted, use client",
+                    self.path
+                ),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// map method and path to command, like "restart child1"
+pub fn route(method: &str, path: &str) -> Result<String> {
+    let segments = path
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .collect::<Vec<&str>>();
+
+    let command = match segments.as_slice() {
+        ["children"] => ("GET", "status".to_string()),
+        ["children", name] => ("GET", format!("status {}", name)),
+        ["children", name, op @ ("start" | "stop" | "restart" | "trystart")] => {
+            ("POST", format!("{} {}", op, name))
+        }
+        ["info"] => ("GET", "info".to_string()),
+        ["keys"] => ("GET", "keys".to_string()),
+        ["shutdown"] => ("POST", "kill".to_string()),
+        _ => {
+            return Err(ioError::new(
+                ErrorKind::NotFound,
+                format!("no such api: {}", path),
+            ))
+        }
+    };
+
+    if command.0 != method {
+        return Err(ioError::new(
+            ErrorKind::Unsupported,
+            format!("{} only supports {}", path, command.0),
+        ));
+    }
+    Ok(command.1)
+}
+
+/// HTTP status code of error
+pub fn status_code(e: &ioError) -> u16 {
+    match e.kind() {
+        ErrorKind::NotFound => 404,
+        ErrorKind::InvalidInput | ErrorKind::InvalidData => 400,
+        ErrorKind::PermissionDenied if acl::is_denied(e) => 403,
+        ErrorKind::PermissionDenied => 401,
+        ErrorKind::Unsupported => 405,
+        _ => 500,
+    }
+}
+
+fn reason(code: u16) -> &'static str {
+    match code {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        _ => "Internal Server Error",
+    }
+}
+
+/// write json body with status code, connection is closed after
+pub fn write_response<W: Write>(w: &mut W, code: u16, body: &str) -> Result<()> {
+    write!(
+        w,
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        code,
+        reason(code),
+        body.len(),
+        body
+    )?;
+    w.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_request() {
+        let raw = "POST /children/child1/restart?x=1 HTTP/1.1\r\n\
+                   Host: localhost\r\n\
+                   Authorization: Supervisor YWxpY2U7AQI=\r\n\
+                   Content-Length: 2\r\n\r\n{}";
+        let req = Request::read_from(&mut raw.as_bytes()).unwrap();
+        assert_eq!(req.method, "POST");
+        assert_eq!(req.path, "/children/child1/restart");
+        assert_eq!(req.header("HOST"), Some("localhost"));
+        assert_eq!(req.auth_token().unwrap(), b"alice;\x01\x02".to_vec());
+
+        let req = Request::read_from(&mut "GET /info HTTP/1.1\r\n\r\n".as_bytes()).unwrap();
+        assert_eq!(
+            req.auth_token().unwrap_err().kind(),
+            ErrorKind::PermissionDenied
+        );
+
+        assert!(Request::read_from(&mut "restart child1".as_bytes()).is_err());
+        assert_eq!(
+            Request::read_from(&mut "\r\n\r\n".as_bytes())
+                .unwrap_err()
+                .kind(),
+            ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn test_refuse_read() {
+        let req = Request::read_from(&mut "GET /children HTTP/1.1\r\n\r\n".as_bytes()).unwrap();
+        assert_eq!(status_code(&req.refuse_read().unwrap_err()), 405);
+
+        let req =
+            Request::read_from(&mut "POST /children/c1/stop HTTP/1.1\r\n\r\n".as_bytes()).unwrap();
+        assert!(req.refuse_read().is_ok());
+    }
+
+    #[test]
+    fn test_route() {
+        assert_eq!(route("GET", "/children").unwrap(), "status");
+        assert_eq!(route("GET", "/children/child1/").unwrap(), "status child1");
+        assert_eq!(
+            route("POST", "/children/child1/trystart").unwrap(),
+            "trystart child1"
+        );
+        assert_eq!(route("GET", "/info").unwrap(), "info");
+        assert_eq!(route("GET", "/keys").unwrap(), "keys");
+        assert_eq!(route("POST", "/shutdown").unwrap(), "kill");
+
+        let e = route("GET", "/shutdown").unwrap_err();
+        assert_eq!(status_code(&e), 405);
+        let e = route("POST", "/children/child1/kill").unwrap_err();
+        assert_eq!(status_code(&e), 404);
+    }
+}
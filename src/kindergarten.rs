@@ -1,9 +1,21 @@
+use super::acl::ChildScope;
+use super::audit;
+use super::child::child_history::{Event, History, DEFAULT_HISTORY_SIZE};
+use super::child::child_log::{OutputBuffer, Stream};
+use super::child::child_state::{status_table, Status};
 use super::child::Config;
+use super::keys_handler::NonceCache;
 use super::logger;
 use super::server::*;
+use chrono::{DateTime, Local};
 use std::collections::HashMap;
 use std::io::{Error as ioError, ErrorKind, Result};
 use std::process::Child;
+use std::sync::mpsc::Receiver;
+use std::sync::{Arc, Mutex};
+
+/// default size of each child's output buffer, 0 is off
+pub const DEFAULT_LOG_BUFFER_SIZE: usize = 0;
 
 #[derive(Debug)]
 pub struct Kindergarten {
@@ -17,8 +29,43 @@ pub struct Kindergarten {
     /// cannot accept duplicated name
     name_list: HashMap<String, u32>,
 
+    /// child_name -> recent output of child
+    /// won't be deleted after child stopped
+    output_buffers: HashMap<String, Arc<Mutex<OutputBuffer>>>,
+
+    /// size of each output buffer in bytes
+    pub log_buffer_size: usize,
+
+    /// child_name -> lifecycle events of child
+    /// won't be deleted after child stopped
+    histories: HashMap<String, History>,
+
+    /// how many events kept for each child
+    pub history_size: usize,
+
+    /// child_name -> state of child, including stopped children
+    states: HashMap<String, Status>,
+
+    /// who runs the current command, recorded in history
+    pub operator: String,
+
     /// encrypt mode
     pub encrypt_mode: bool,
+
+    /// nonces of recent signed commands, for rejecting replays
+    pub nonces: NonceCache,
+
+    /// key name -> last time a command of it is accepted, for keys command
+    pub keys_last_used: HashMap<String, DateTime<Local>>,
+}
+
+/// if config file of running child changed on disk, for check and status
+fn drift_note(config: &Config) -> &'static str {
+    match config.config_drifted() {
+        Ok(true) => "yes",
+        Ok(false) => "no",
+        Err(_) => "file missing",
+    }
 }
 
 impl Kindergarten {
@@ -28,7 +75,17 @@ impl Kindergarten {
             id_list: HashMap::new(),
             name_list: HashMap::new(),
 
+            output_buffers: HashMap::new(),
+            log_buffer_size: DEFAULT_LOG_BUFFER_SIZE,
+
+            histories: HashMap::new(),
+            history_size: DEFAULT_HISTORY_SIZE,
+            states: HashMap::new(),
+            operator: "server".to_string(),
+
             encrypt_mode: false,
+            nonces: NonceCache::default(),
+            keys_last_used: HashMap::new(),
         }
     }
 
@@ -50,7 +107,7 @@ impl Kindergarten {
 
     /// pre_hook_chain is Vec<(command, name, config)>
     /// if one of them failed in processing, this function will return without run commands after it.
-    pub fn handle_pre_hook(&mut self, pre_hook_chain: Vec<(String, String, Config)>) -> Result<()> {
+    pub fn handle_pre_hook(&mut self, pre_hook_chain: &[(String, String, Config)]) -> Result<()> {
         for each in pre_hook_chain.iter() {
             match each.0.as_ref() {
                 "start" | "Start" => self.start(&each.1, &mut each.2.clone())?,
@@ -64,6 +121,19 @@ impl Kindergarten {
 
     /// start child.
     pub fn start(&mut self, name: &String, config: &mut Config) -> Result<()> {
+        let pid = self.spawn(name, config)?;
+        self.record(
+            name,
+            Event::Started {
+                pid,
+                by: self.operator.clone(),
+            },
+        );
+        Ok(())
+    }
+
+    /// start child process and register it, return its pid
+    fn spawn(&mut self, name: &String, config: &mut Config) -> Result<u32> {
         // check inside again (because "start" in server has checked once) here...
         // ...because prehook need check too, but it does not check in server
         if let Some(_) = self.has_child(name) {
@@ -74,15 +144,27 @@ impl Kindergarten {
         };
 
         // start new child
-        match start_new_child(config) {
+        let buffer = self.output_buffer(name);
+        self.status_mut(name).starting();
+        match start_new_child(config, buffer) {
             Ok(child) => {
                 //update kindergarten
                 let new_id = child.id();
+                self.status_mut(name)
+                    .started(new_id, config.start_time.unwrap_or_else(Local::now));
+                logger::info(
+                    "child started",
+                    &[("child", name), ("pid", &new_id.to_string())],
+                );
                 self.update(new_id, name, child, config.clone());
-                Ok(())
+                Ok(new_id)
             }
             Err(e) => {
-                println!("{:?}", e);
+                self.status_mut(name).spawn_failed();
+                logger::error(
+                    "cannot start child",
+                    &[("child", name), ("error", &e.to_string())],
+                );
                 return Err(ioError::new(
                     ErrorKind::InvalidData,
                     format!("Cannot start child {}", name),
@@ -100,10 +182,20 @@ impl Kindergarten {
     /// 3. update kindergarten
     pub fn restart(&mut self, name: &String, config: &mut Config) -> Result<()> {
         // if this child is not running, it cannot be stopped, return err
-        self.stop(name)?;
+        let old_pid = self.kill(name)?;
+        let by = self.operator.clone();
 
         // start new child
-        self.start(name, config)
+        match self.spawn(name, config) {
+            Ok(pid) => {
+                self.record(name, Event::Restarted { old_pid, pid, by });
+                Ok(())
+            }
+            Err(e) => {
+                self.record(name, Event::Stopped { pid: old_pid, by });
+                Err(e)
+            }
+        }
     }
 
     /// stop child, and delete it in kg, after this method, do not need delete child
@@ -118,27 +210,51 @@ impl Kindergarten {
             ));
         }
 
+        let pid = self.kill(name)?;
+        self.record(
+            name,
+            Event::Stopped {
+                pid,
+                by: self.operator.clone(),
+            },
+        );
+        Ok(())
+    }
+
+    /// kill running child and delete it in kg, return its pid
+    fn kill(&mut self, name: &String) -> Result<u32> {
         // get id
-        let id = match self.name_list.get(name).as_ref() {
-            Some(id) => id,
-            None => &1,
+        let id = match self.name_list.get(name) {
+            Some(id) => *id,
+            None => 1,
         };
 
         // check if this name of child in kindergarden
-        if *id == 1 {
+        if id == 1 {
             return Err(ioError::new(
                 ErrorKind::InvalidData,
                 format!("{} not exsit, cannot stop", name),
             ));
         }
 
+        let last_state = self.status_mut(name).state;
+        self.status_mut(name).stopping();
+
         // get child_handle
         let store_val = self.id_list.get_mut(&id).unwrap();
         let child_handle = &mut (store_val.0);
 
         // kill old child
         if let Err(e) = child_handle.kill() {
-            println!("{:?}", e);
+            self.status_mut(name).state = last_state;
+            logger::error(
+                "cannot kill child",
+                &[
+                    ("child", name),
+                    ("pid", &id.to_string()),
+                    ("error", &e.to_string()),
+                ],
+            );
             return Err(ioError::new(
                 ErrorKind::InvalidData,
                 format!("Cannot kill child {}, id is {}, err is {}", name, id, e),
@@ -147,8 +263,13 @@ impl Kindergarten {
 
         match child_handle.wait() {
             Ok(_) => {
+                logger::info(
+                    "child stopped",
+                    &[("child", name), ("pid", &id.to_string())],
+                );
                 self.delete_by_name(name)?;
-                Ok(())
+                self.status_mut(name).stopped();
+                Ok(id)
             }
             Err(e) => Err(ioError::new(
                 ErrorKind::InvalidData,
@@ -184,32 +305,147 @@ impl Kindergarten {
             ));
         }
 
-        let mut cache: Vec<String> = vec![];
+        let mut cache: Vec<(String, Event, bool)> = vec![];
         for (name, id) in self.name_list.iter() {
             let store_val = self.id_list.get_mut(id).unwrap();
             let child_handle = &mut (store_val.0);
 
             // try to find those children dead
             match child_handle.try_wait()? {
-                Some(_) => {
+                Some(status) => {
                     let _ = child_handle.wait();
-                    cache.push(name.clone());
+                    cache.push((
+                        name.clone(),
+                        Event::exited(*id, status),
+                        store_val.1.is_repeat(),
+                    ));
                 }
                 None => (),
             }
         }
 
-        for name in cache {
+        for status in self.states.values_mut() {
+            status.refresh();
+        }
+
+        for (name, event, will_retry) in cache {
             self.delete_by_name(&name)?;
-            println!(
-                "{}",
-                logger::timelog(&format!("{} has stopped, delete from kindergarden", name))
+            if let Event::Exited { code, signal, .. } = event {
+                self.status_mut(&name).exited(code, signal, will_retry);
+            }
+            logger::info(
+                "child has stopped, delete from kindergarden",
+                &[("child", &name), ("event", &event.to_string())],
             );
+            self.record(&name, event);
         }
 
         Ok(())
     }
 
+    /// status of child, new child is Stopped
+    pub fn status_mut(&mut self, name: &str) -> &mut Status {
+        self.states.entry(name.to_string()).or_default()
+    }
+
+    /// handler command "status", table of configured children and
+    /// children kindergarten has seen. Empty name means all
+    pub fn status(&mut self, configured: Vec<String>, name: &str) -> Result<String> {
+        Ok(status_table(&self.status_rows(configured, name)?))
+    }
+
+    /// status of configured children and children kindergarten has seen.
+    /// Empty name means all
+    pub fn status_rows(
+        &mut self,
+        configured: Vec<String>,
+        name: &str,
+    ) -> Result<Vec<(String, Status)>> {
+        self.check_around()?;
+
+        let mut names = configured;
+        names.extend(self.states.keys().cloned());
+        names.sort();
+        names.dedup();
+        if !name.is_empty() {
+            if !names.iter().any(|n| n == name) {
+                return Err(ioError::new(
+                    ErrorKind::NotFound,
+                    format!("{} not exsit", name),
+                ));
+            }
+            names = vec![name.to_string()];
+        }
+
+        Ok(names
+            .into_iter()
+            .map(|n| {
+                let mut status = self.states.get(&n).cloned().unwrap_or_default();
+                if let Some(id) = self.name_list.get(&n) {
+                    status.config_changed = Some(drift_note(&self.id_list[id].1).to_string());
+                }
+                (n, status)
+            })
+            .collect())
+    }
+
+    /// add lifecycle event to child's history
+    pub fn record(&mut self, name: &str, event: Event) {
+        let size = self.history_size;
+        self.histories
+            .entry(name.to_string())
+            .or_insert_with(|| History::new(size))
+            .push(event);
+    }
+
+    /// handler command "diff", running config of child against its config on disk
+    pub fn diff(&mut self, name: &String, on_disk: Result<Config>) -> Result<String> {
+        let running = match self.get_child_config(name) {
+            Some(c) => c,
+            None => {
+                return Err(ioError::new(
+                    ErrorKind::NotFound,
+                    format!("{} is not running, nothing to diff", name),
+                ))
+            }
+        };
+
+        if !running.config_drifted()? {
+            return Ok(format!(
+                "config of {} has not changed since it started\n",
+                name
+            ));
+        }
+
+        let on_disk = on_disk?;
+        if !running.differs_from(&on_disk) {
+            return Ok(format!(
+                "config file of {} has changed, but no field is different\n",
+                name
+            ));
+        }
+
+        let mut res = format!("==Diff of {} (running -> on disk)==\n", name);
+        for (field, old, new) in running.diff(&on_disk) {
+            res.push_str(&format!("{}:\n  - {}\n  + {}\n", field, old, new));
+        }
+        Ok(res)
+    }
+
+    /// handler command "history"
+    pub fn history(&mut self, name: &str) -> Result<String> {
+        // catch children exited since last command
+        self.check_around()?;
+
+        match self.histories.get(name) {
+            Some(h) => Ok(format!("==History of {}==\n{}", name, h)),
+            None => Err(ioError::new(
+                ErrorKind::NotFound,
+                format!("no history of {}", name),
+            )),
+        }
+    }
+
     /// delete by name, won't return error if no name
     pub fn delete_by_name(&mut self, name: &String) -> Result<()> {
         if let Some(id) = self.name_list.remove(name) {
@@ -219,6 +455,11 @@ impl Kindergarten {
         Ok(())
     }
 
+    /// command of key is accepted now
+    pub fn key_used(&mut self, name: &str) {
+        self.keys_last_used.insert(name.to_string(), Local::now());
+    }
+
     /// check if kindergarten has this child, return option child id
     pub fn has_child(&mut self, name: &String) -> Option<&u32> {
         self.name_list.get(name)
@@ -236,35 +477,41 @@ impl Kindergarten {
     }
 
     /// handler command "check"
-    pub fn check_status(&mut self, name: &String) -> Result<String> {
+    pub fn check_status(&mut self, name: &String, scope: &ChildScope) -> Result<String> {
         // first check_around
         self.check_around()?;
 
         let mut res = String::from("==Check Results Below==\n");
         if name == "" {
-            for (name, id) in self.name_list.iter() {
+            for (name, id) in self.name_list.iter().filter(|(n, _)| scope.contains(n)) {
+                let config = &self.id_list.get(id).unwrap().1;
                 res.push_str(&format!(
                     "child name: {}
 processing id: {}
+config changed on disk: {}
 config detail:
 {}
 =======================\n",
                     name,
                     id,
-                    self.id_list.get(id).unwrap().1
+                    drift_note(config),
+                    config
                 ));
             }
         } else {
             if let Some(id) = self.name_list.get(name) {
+                let config = &self.id_list.get(id).unwrap().1;
                 res.push_str(&format!(
                     "child name: {}
 processing id: {}
+config changed on disk: {}
 config detail:
 {}
 =======================\n",
                     name,
                     id,
-                    self.id_list.get(id).unwrap().1
+                    drift_note(config),
+                    config
                 ))
             }
         }
@@ -276,6 +523,103 @@ config detail:
         }
     }
 
+    /// reopen log files written by server, for external logrotate.
+    /// empty name or "all" means all running children
+    pub fn reopen_logs(&mut self, name: &String) -> Result<String> {
+        let names = if name.is_empty() || name == "all" {
+            self.all_running_children()
+        } else if self.has_child(name).is_some() {
+            vec![name.clone()]
+        } else {
+            return Err(ioError::new(
+                ErrorKind::InvalidData,
+                format!("{} not exsit, cannot reopen its output", name),
+            ));
+        };
+
+        let mut res = String::new();
+        if name.is_empty() || name == "all" {
+            if let Some(path) = audit::reopen()? {
+                res.push_str(&format!("reopen audit log {}\n", path));
+            }
+        }
+
+        for name in names {
+            let id = self.name_list[&name];
+            let config = &self.id_list[&id].1;
+            for log in &config.log_files {
+                let mut log = log.lock().unwrap();
+                log.reopen()?;
+                res.push_str(&format!("reopen {} of {}\n", log.path(), name));
+            }
+        }
+
+        if res.is_empty() {
+            Ok(String::from(
+                "no output file written by server need to reopen\n",
+            ))
+        } else {
+            Ok(res)
+        }
+    }
+
+    /// get output buffer of child, make new one if it doesn't have.
+    /// None if output buffer is off
+    pub fn output_buffer(&mut self, name: &str) -> Option<Arc<Mutex<OutputBuffer>>> {
+        let size = self.log_buffer_size;
+        if size == 0 {
+            return None;
+        }
+        Some(Arc::clone(
+            self.output_buffers
+                .entry(name.to_string())
+                .or_insert_with(|| Arc::new(Mutex::new(OutputBuffer::new(size)))),
+        ))
+    }
+
+    /// error of logs when output of child is not in buffer
+    fn no_output(&self, name: &str) -> ioError {
+        if self.log_buffer_size == 0 {
+            ioError::new(
+                ErrorKind::Unsupported,
+                "output of children is not kept, set log_buffer_size of server config",
+            )
+        } else {
+            ioError::new(
+                ErrorKind::NotFound,
+                format!("no output of {} recorded", name),
+            )
+        }
+    }
+
+    /// handler command "logs", return last lines of child's output
+    pub fn logs(&mut self, name: &str, stream: Stream, lines: usize) -> Result<String> {
+        let buffer = match self.output_buffers.get(name) {
+            Some(b) => b,
+            None => return Err(self.no_output(name)),
+        };
+
+        let mut res = format!("==Last {} lines of {} std{}==\n", lines, name, stream);
+        res.push_str(&String::from_utf8_lossy(
+            &buffer.lock().unwrap().tail(stream, lines),
+        ));
+        Ok(res)
+    }
+
+    /// handler command "logs --follow", return last lines of child's output
+    /// and receiver of new output
+    pub fn follow(
+        &mut self,
+        name: &str,
+        stream: Stream,
+        lines: usize,
+    ) -> Result<(Vec<u8>, Receiver<Vec<u8>>)> {
+        match self.output_buffers.get(name) {
+            Some(b) => Ok(b.lock().unwrap().follow(stream, lines)),
+            None => Err(self.no_output(name)),
+        }
+    }
+
     /// Get all running children names
     pub fn all_running_children(&self) -> Vec<String> {
         self.name_list.keys().cloned().collect()
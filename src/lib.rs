@@ -1,7 +1,16 @@
+pub mod acl;
+pub mod audit;
 pub mod child;
 pub mod client;
+pub mod http;
 pub mod keys_handler;
 pub mod kindergarten;
 pub mod logger;
+pub mod protocol;
+pub mod response;
 pub mod server;
+#[cfg(test)]
+mod test_util;
 pub mod timer;
+pub mod tls;
+pub mod unix_socket;
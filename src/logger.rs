@@ -1,4 +1,167 @@
+//! logger of server itself, with levels, text or json format, and structured fields.
+//!
+//! ```ignore
+//! logger::info("start child success", &[("child", "child1"), ("pid", "1234")]);
+//! ```
+//!
+//! text format: `[2021-06-01 10:00:00] INFO start child success child=child1 pid=1234`
+//!
+//! json format: `{"time":"2021-06-01T10:00:00+08:00","level":"info","msg":"start child success","child":"child1","pid":"1234"}`
 use chrono::prelude::*;
+use serde_json::{Map, Value};
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Error as ioError, ErrorKind, Result, Write};
+use std::str::FromStr;
+use std::sync::Mutex;
+
+/// Log level
+#[derive(Debug, Copy, Clone, PartialEq, PartialOrd)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+impl FromStr for Level {
+    type Err = ioError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "error" => Ok(Level::Error),
+            "warn" => Ok(Level::Warn),
+            "info" => Ok(Level::Info),
+            "debug" => Ok(Level::Debug),
+            _ => Err(ioError::new(
+                ErrorKind::InvalidInput,
+                format!("log level {} is illegal", s),
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Level::Error => write!(f, "error"),
+            Level::Warn => write!(f, "warn"),
+            Level::Info => write!(f, "info"),
+            Level::Debug => write!(f, "debug"),
+        }
+    }
+}
+
+/// Log format
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Format {
+    Text,
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = ioError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            _ => Err(ioError::new(
+                ErrorKind::InvalidInput,
+                format!("log format {} is illegal", s),
+            )),
+        }
+    }
+}
+
+struct Logger {
+    level: Level,
+    format: Format,
+
+    /// None means stdout
+    file: Option<File>,
+}
+
+static LOGGER: Mutex<Logger> = Mutex::new(Logger {
+    level: Level::Info,
+    format: Format::Text,
+    file: None,
+});
+
+/// setup logger with server config, log to stdout if no file given
+pub fn init(level: Level, format: Format, file: Option<&str>) -> Result<()> {
+    let file = match file {
+        Some(path) => Some(OpenOptions::new().append(true).create(true).open(path)?),
+        None => None,
+    };
+
+    let mut logger = LOGGER.lock().unwrap();
+    logger.level = level;
+    logger.format = format;
+    logger.file = file;
+    Ok(())
+}
+
+/// log message with fields
+pub fn log(level: Level, msg: &str, fields: &[(&str, &str)]) {
+    let mut logger = LOGGER.lock().unwrap_or_else(|e| e.into_inner());
+    if level > logger.level {
+        return;
+    }
+
+    let mut line = match logger.format {
+        Format::Text => text_line(level, msg, fields),
+        Format::Json => json_line(level, msg, fields),
+    };
+    line.push('\n');
+
+    let _ = match logger.file.as_mut() {
+        Some(f) => f.write_all(line.as_bytes()),
+        None => io::stdout().lock().write_all(line.as_bytes()),
+    };
+}
+
+pub fn error(msg: &str, fields: &[(&str, &str)]) {
+    log(Level::Error, msg, fields)
+}
+
+pub fn warn(msg: &str, fields: &[(&str, &str)]) {
+    log(Level::Warn, msg, fields)
+}
+
+pub fn info(msg: &str, fields: &[(&str, &str)]) {
+    log(Level::Info, msg, fields)
+}
+
+pub fn debug(msg: &str, fields: &[(&str, &str)]) {
+    log(Level::Debug, msg, fields)
+}
+
+fn text_line(level: Level, msg: &str, fields: &[(&str, &str)]) -> String {
+    let mut line = timelog(&format!("{} {}", level.to_string().to_uppercase(), msg));
+    for (k, v) in fields {
+        if v.contains(' ') || v.contains('\n') {
+            line.push_str(&format!(" {}={:?}", k, v));
+        } else {
+            line.push_str(&format!(" {}={}", k, v));
+        }
+    }
+    line
+}
+
+fn json_line(level: Level, msg: &str, fields: &[(&str, &str)]) -> String {
+    let mut obj = Map::new();
+    obj.insert(
+        "time".to_string(),
+        Value::from(Local::now().to_rfc3339_opts(SecondsFormat::Secs, false)),
+    );
+    obj.insert("level".to_string(), Value::from(level.to_string()));
+    obj.insert("msg".to_string(), Value::from(msg));
+    for (k, v) in fields {
+        obj.insert(k.to_string(), Value::from(*v));
+    }
+    Value::Object(obj).to_string()
+}
 
 pub fn timelog(s: &str) -> String {
     let mut dt = Local::now().format("[%Y-%m-%d %H:%M:%S] ").to_string();
@@ -6,6 +169,16 @@ pub fn timelog(s: &str) -> String {
     dt
 }
 
+/// same as timelog, but timestamp is RFC3339 format
+pub fn timelog_rfc3339(s: &str) -> String {
+    let mut dt = format!(
+        "[{}] ",
+        Local::now().to_rfc3339_opts(SecondsFormat::Secs, false)
+    );
+    dt.push_str(s);
+    dt
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -13,5 +186,27 @@ mod tests {
     #[test]
     fn test_time_print() {
         println!("{}", timelog("aaaa"));
+        println!("{}", timelog_rfc3339("aaaa"));
+    }
+
+    #[test]
+    fn test_log_lines() {
+        let text = text_line(
+            Level::Warn,
+            "child stopped",
+            &[("child", "child1"), ("reason", "exit code 1")],
+        );
+        assert!(text.ends_with("] WARN child stopped child=child1 reason=\"exit code 1\""));
+
+        let json: Value = serde_json::from_str(&json_line(
+            Level::Info,
+            "start \"child\"",
+            &[("child", "child1"), ("pid", "1234")],
+        ))
+        .unwrap();
+        assert_eq!(json["level"], "info");
+        assert_eq!(json["msg"], "start \"child\"");
+        assert_eq!(json["child"], "child1");
+        assert_eq!(json["pid"], "1234");
     }
 }
@@ -0,0 +1,499 @@
+//! Wire protocol between client and server.
+//!
+//! Each frame is `MAGIC | version (1 byte) | length (4 bytes, big endian) | payload`.
+//! Client starts with an empty hello frame of its highest version, server replies
+//! an empty frame of the version both sides speak. Then client sends one request
+//! frame, server sends response frames until it closes the connection.
+//!
+//! Server still accepts legacy clients, which send command without frame.
+//!
+//! In encrypt mode, payloads of response frames can be encrypted by a PayloadCipher.
+//! Errors before command is verified are sent in plain text, marked as unsealed.
+//! Encrypted response ends with a sealed end payload.
+use std::error::Error;
+use std::fmt;
+use std::io::{Error as ioError, ErrorKind, Read, Result, Write};
+use std::net::TcpStream;
+use std::{thread, time};
+
+/// first bytes of every frame
+pub const MAGIC: &[u8; 4] = b"SVRS";
+
+/// highest version this side speaks
+pub const VERSION: u8 = 1;
+
+/// lowest version this side speaks
+pub const MIN_VERSION: u8 = 1;
+
+/// first bytes of plain payload, sent when client may wait for encrypted ones
+pub const UNSEALED_MAGIC: &[u8; 4] = b"SVRU";
+
+/// biggest payload accepted
+pub const MAX_FRAME_SIZE: usize = 1024 * 1024;
+
+const HEADER_SIZE: usize = 9;
+
+/// buffer size of legacy request, 100 command length + 4096 key length
+const LEGACY_BUFFER_SIZE: usize = 100 + 4096;
+
+/// encryption of frame payloads, over framed protocol only
+pub trait PayloadCipher: Send {
+    /// payload this side sends
+    fn seal(&mut self, payload: &[u8]) -> Result<Vec<u8>>;
+
+    /// payload this side reads
+    fn open(&mut self, payload: &[u8]) -> Result<Vec<u8>>;
+
+    /// last payload this side sends, reader knows nothing is cut off by it
+    fn seal_end(&mut self) -> Result<Vec<u8>>;
+
+    /// no more payloads to read, error if end payload is not read
+    fn check_end(&self) -> Result<()>;
+}
+
+/// Connection speaks framed protocol or legacy one
+pub struct Channel<S: Read + Write> {
+    stream: S,
+
+    /// negotiated version, None is legacy protocol
+    version: Option<u8>,
+
+    /// rest of payload of current frame, for Read
+    pending: Vec<u8>,
+
+    /// payloads are encrypted after it is set
+    cipher: Option<Box<dyn PayloadCipher>>,
+}
+
+impl<S: Read + Write> Channel<S> {
+    /// framed channel after handshake
+    pub fn framed(stream: S, version: u8) -> Self {
+        Channel {
+            stream,
+            version: Some(version),
+            pending: vec![],
+            cipher: None,
+        }
+    }
+
+    /// legacy channel, no frame at all
+    pub fn legacy(stream: S) -> Self {
+        Channel {
+            stream,
+            version: None,
+            pending: vec![],
+            cipher: None,
+        }
+    }
+
+    pub fn version(&self) -> Option<u8> {
+        self.version
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.stream
+    }
+
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.stream
+    }
+
+    /// encrypt payloads sent and read from now on.
+    /// legacy protocol can't send sealed payloads, and reads none
+    pub fn set_cipher(&mut self, cipher: Box<dyn PayloadCipher>) {
+        self.cipher = Some(cipher);
+    }
+
+    /// send data, as one frame if it is framed protocol
+    pub fn send(&mut self, data: &[u8]) -> Result<()> {
+        match self.version {
+            Some(v) => match self.cipher.as_mut() {
+                Some(c) => write_frame(&mut self.stream, v, &c.seal(data)?)?,
+                None => write_frame(&mut self.stream, v, data)?,
+            },
+            None => self.stream.write_all(data)?,
+        }
+        self.stream.flush()
+    }
+
+    /// send data in plain text even if cipher is set, marked as unsealed.
+    /// client waits for encrypted payloads takes it as unauthenticated error
+    pub fn send_unsealed(&mut self, data: &[u8]) -> Result<()> {
+        match self.version {
+            Some(v) => {
+                let mut payload = UNSEALED_MAGIC.to_vec();
+                payload.extend_from_slice(data);
+                write_frame(&mut self.stream, v, &payload)?
+            }
+            None => self.stream.write_all(data)?,
+        }
+        self.stream.flush()
+    }
+
+    /// send sealed end of response if cipher is set
+    pub fn finish(&mut self) -> Result<()> {
+        match (self.version, self.cipher.as_mut()) {
+            (Some(v), Some(c)) => {
+                write_frame(&mut self.stream, v, &c.seal_end()?)?;
+                self.stream.flush()
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// server side, read the request of client
+    pub fn read_request(&mut self) -> Result<Vec<u8>> {
+        match self.version {
+            Some(v) => match read_frame(&mut self.stream)? {
+                Some((fv, payload)) if fv == v => Ok(payload),
+                Some((fv, _)) => Err(ioError::new(
+                    ErrorKind::InvalidData,
+                    format!("frame version {} is not negotiated version {}", fv, v),
+                )),
+                None => Err(ioError::new(
+                    ErrorKind::UnexpectedEof,
+                    "client closed before sending request",
+                )),
+            },
+            None => {
+                // legacy request is read once and padded with zeros
+                let mut buf = vec![0; LEGACY_BUFFER_SIZE];
+                let n = self.stream.read(&mut buf)?;
+                buf.truncate(n);
+                while let Some(0) = buf.last() {
+                    buf.pop();
+                }
+                Ok(buf)
+            }
+        }
+    }
+}
+
+/// read payloads of all response frames, like a plain stream
+impl<S: Read + Write> Read for Channel<S> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if self.version.is_none() {
+            // legacy response can't be sealed, client waits for sealed one is refused
+            if self.cipher.is_some() {
+                let mut plain = String::new();
+                self.stream.read_to_string(&mut plain)?;
+                return Err(ioError::new(
+                    ErrorKind::PermissionDenied,
+                    Unauthenticated(plain.trim().to_string()),
+                ));
+            }
+            return self.stream.read(buf);
+        }
+
+        while self.pending.is_empty() {
+            match read_frame(&mut self.stream)? {
+                Some((_, payload)) => {
+                    self.pending =
+                        match (payload.strip_prefix(UNSEALED_MAGIC), self.cipher.as_mut()) {
+                            // anyone on the way can send plain payload, it is not a response
+                            (Some(plain), Some(_)) => {
+                                return Err(ioError::new(
+                                    ErrorKind::PermissionDenied,
+                                    Unauthenticated(
+                                        String::from_utf8_lossy(plain).trim().to_string(),
+                                    ),
+                                ))
+                            }
+                            (Some(plain), None) => plain.to_vec(),
+                            (None, Some(c)) => c.open(&payload)?,
+                            (None, None) => payload,
+                        }
+                }
+                None => {
+                    if let Some(c) = self.cipher.as_ref() {
+                        c.check_end()?;
+                    }
+                    return Ok(0);
+                }
+            }
+        }
+
+        let n = buf.len().min(self.pending.len());
+        buf[..n].copy_from_slice(&self.pending[..n]);
+        self.pending.drain(..n);
+        Ok(n)
+    }
+}
+
+/// plain error client gets when it waits for encrypted response
+#[derive(Debug)]
+pub struct Unauthenticated(String);
+
+impl fmt::Display for Unauthenticated {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "unauthenticated error, it may not come from server: {}",
+            self.0
+        )
+    }
+}
+
+impl Error for Unauthenticated {}
+
+/// error is plain payload client gets instead of encrypted one
+pub fn is_unauthenticated(e: &ioError) -> bool {
+    e.get_ref()
+        .is_some_and(|inner| inner.is::<Unauthenticated>())
+}
+
+/// server side, check if client speaks framed protocol and do handshake
+pub fn accept(stream: &mut TcpStream) -> Result<Channel<&mut TcpStream>> {
+    if !starts_with_magic(stream)? {
+        return Ok(Channel::legacy(stream));
+    }
+    accept_framed(stream)
+}
+
+/// server side handshake, for streams have no legacy clients (unix socket)
+pub fn accept_framed<S: Read + Write>(stream: &mut S) -> Result<Channel<&mut S>> {
+    let client_version = match read_frame(stream)? {
+        Some((v, _)) => v,
+        None => return Err(ioError::new(ErrorKind::UnexpectedEof, "no hello frame")),
+    };
+
+    let version = client_version.min(VERSION);
+    if version < MIN_VERSION {
+        write_frame(
+            stream,
+            0,
+            format!(
+                "client protocol version {} is not supported, server needs {} to {}",
+                client_version, MIN_VERSION, VERSION
+            )
+            .as_bytes(),
+        )?;
+        return Err(ioError::new(
+            ErrorKind::InvalidData,
+            format!(
+                "client protocol version {} is not supported",
+                client_version
+            ),
+        ));
+    }
+
+    write_frame(stream, version, &[])?;
+    Ok(Channel::framed(stream, version))
+}
+
+/// client side handshake, return negotiated version.
+/// None means server only speaks legacy protocol
+pub fn handshake<S: Read + Write>(stream: &mut S) -> Result<Option<u8>> {
+    write_frame(stream, VERSION, &[])?;
+
+    let mut header = [0; HEADER_SIZE];
+    match stream.read_exact(&mut header) {
+        Ok(_) => (),
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    if &header[..4] != MAGIC {
+        return Ok(None);
+    }
+
+    let (version, len) = parse_header(&header)?;
+    if version < MIN_VERSION {
+        let mut reason = vec![0; len];
+        stream.read_exact(&mut reason)?;
+        return Err(ioError::new(
+            ErrorKind::InvalidData,
+            String::from_utf8_lossy(&reason).to_string(),
+        ));
+    }
+
+    Ok(Some(version))
+}
+
+/// wait first 4 bytes (without consuming them) and check magic
+fn starts_with_magic(stream: &TcpStream) -> Result<bool> {
+    let mut head = [0; 4];
+    for _ in 0..100 {
+        let n = stream.peek(&mut head)?;
+        if n == 0 || !MAGIC.starts_with(&head[..n]) {
+            return Ok(false);
+        }
+        if n == head.len() {
+            return Ok(true);
+        }
+        thread::sleep(time::Duration::from_millis(10));
+    }
+    Ok(false)
+}
+
+pub fn write_frame<W: Write>(w: &mut W, version: u8, payload: &[u8]) -> Result<()> {
+    if payload.len() > MAX_FRAME_SIZE {
+        return Err(ioError::new(
+            ErrorKind::InvalidInput,
+            format!("frame is bigger than {} bytes", MAX_FRAME_SIZE),
+        ));
+    }
+
+    let mut frame = Vec::with_capacity(HEADER_SIZE + payload.len());
+    frame.extend_from_slice(MAGIC);
+    frame.push(version);
+    frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    frame.extend_from_slice(payload);
+    w.write_all(&frame)
+}
+
+/// read one frame, None if connection closed before it
+pub fn read_frame<R: Read>(r: &mut R) -> Result<Option<(u8, Vec<u8>)>> {
+    let mut header = [0; HEADER_SIZE];
+    match r.read_exact(&mut header) {
+        Ok(_) => (),
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    if &header[..4] != MAGIC {
+        return Err(ioError::new(ErrorKind::InvalidData, "frame magic is wrong"));
+    }
+
+    let (version, len) = parse_header(&header)?;
+    let mut payload = vec![0; len];
+    r.read_exact(&mut payload)?;
+    Ok(Some((version, payload)))
+}
+
+fn parse_header(header: &[u8; HEADER_SIZE]) -> Result<(u8, usize)> {
+    let len = u32::from_be_bytes([header[5], header[6], header[7], header[8]]) as usize;
+    if len > MAX_FRAME_SIZE {
+        return Err(ioError::new(
+            ErrorKind::InvalidData,
+            format!("frame is bigger than {} bytes", MAX_FRAME_SIZE),
+        ));
+    }
+    Ok((header[4], len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_frame_roundtrip() {
+        let mut buf = vec![];
+        write_frame(&mut buf, 1, b"restart child1").unwrap();
+        // payload ends with zero should be kept
+        write_frame(&mut buf, 1, &[1, 2, 0]).unwrap();
+
+        let mut r = &buf[..];
+        assert_eq!(
+            read_frame(&mut r).unwrap(),
+            Some((1, b"restart child1".to_vec()))
+        );
+        assert_eq!(read_frame(&mut r).unwrap(), Some((1, vec![1, 2, 0])));
+        assert_eq!(read_frame(&mut r).unwrap(), None);
+
+        let mut bad = &b"HTTP/1.1 200 OK"[..];
+        assert!(read_frame(&mut bad).is_err());
+    }
+
+    #[test]
+    fn test_handshake_and_legacy() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = thread::spawn(move || {
+            let mut result = vec![];
+            for _ in 0..2 {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut ch = accept(&mut stream).unwrap();
+                let req = ch.read_request().unwrap();
+                result.push((ch.version(), req.clone()));
+                ch.send(b"got ").unwrap();
+                ch.send(&req).unwrap();
+            }
+            result
+        });
+
+        // framed client
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let version = handshake(&mut stream).unwrap().unwrap();
+        let mut ch = Channel::framed(&mut stream, version);
+        ch.send(b"check").unwrap();
+        let mut resp = String::new();
+        ch.read_to_string(&mut resp).unwrap();
+        assert_eq!(resp, "got check");
+
+        // legacy client
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(b"Start child1").unwrap();
+        let mut resp = String::new();
+        stream.read_to_string(&mut resp).unwrap();
+        assert_eq!(resp, "got Start child1");
+
+        assert_eq!(
+            server.join().unwrap(),
+            vec![
+                (Some(VERSION), b"check".to_vec()),
+                (None, b"Start child1".to_vec())
+            ]
+        );
+    }
+
+    /// adds a byte before payload
+    struct Shift;
+
+    impl PayloadCipher for Shift {
+        fn seal(&mut self, payload: &[u8]) -> Result<Vec<u8>> {
+            Ok([b">", payload].concat())
+        }
+
+        fn open(&mut self, payload: &[u8]) -> Result<Vec<u8>> {
+            Ok(payload[1..].to_vec())
+        }
+
+        fn seal_end(&mut self) -> Result<Vec<u8>> {
+            self.seal(&[])
+        }
+
+        fn check_end(&self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_unsealed_payload() {
+        let mut ch = Channel::framed(Cursor::new(vec![]), VERSION);
+        ch.set_cipher(Box::new(Shift));
+        ch.send(b"sealed").unwrap();
+        ch.send_unsealed(b"bad key").unwrap();
+        let buf = ch.get_ref().get_ref().clone();
+
+        let mut r = &buf[..];
+        assert_eq!(read_frame(&mut r).unwrap().unwrap().1, b">sealed");
+        assert_eq!(read_frame(&mut r).unwrap().unwrap().1, b"SVRUbad key");
+
+        // client does not wait for encrypted payloads
+        let mut s = String::new();
+        Channel::framed(Cursor::new(buf.clone()), VERSION)
+            .read_to_string(&mut s)
+            .unwrap();
+        assert_eq!(s, ">sealedbad key");
+
+        // client waits for encrypted payloads
+        let mut ch = Channel::framed(Cursor::new(buf), VERSION);
+        ch.set_cipher(Box::new(Shift));
+        let mut sealed = [0; 6];
+        ch.read_exact(&mut sealed).unwrap();
+        assert_eq!(&sealed, b"sealed");
+        let e = ch.read(&mut [0; 16]).unwrap_err();
+        assert!(is_unauthenticated(&e));
+        assert_eq!(
+            e.to_string(),
+            "unauthenticated error, it may not come from server: bad key"
+        );
+
+        // legacy response is never sealed
+        let mut ch = Channel::legacy(Cursor::new(b"bad key\n".to_vec()));
+        ch.set_cipher(Box::new(Shift));
+        assert!(is_unauthenticated(&ch.read(&mut [0; 16]).unwrap_err()));
+    }
+}
@@ -0,0 +1,255 @@
+//! Structured response of server. Text output and json output of client
+//! are both rendered from it.
+use super::acl;
+use super::child::child_state::{ChildState, Status};
+use super::client::{Command, OutputFormat};
+use chrono::prelude::*;
+use serde_json::{Map, Value};
+use std::fmt;
+use std::io::Error as ioError;
+use std::time::Duration;
+
+/// timer started by command, for repeat child
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimerInfo {
+    /// "restart" or "stop"
+    pub action: String,
+    pub interval: Duration,
+    pub next_run: DateTime<Local>,
+}
+
+impl TimerInfo {
+    pub fn new(action: &str, interval: Duration) -> Self {
+        TimerInfo {
+            action: action.to_string(),
+            interval,
+            next_run: Local::now()
+                + chrono::Duration::from_std(interval).unwrap_or_else(|_| chrono::Duration::zero()),
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        let mut obj = Map::new();
+        obj.insert("action".to_string(), Value::from(self.action.as_str()));
+        obj.insert(
+            "interval_secs".to_string(),
+            Value::from(self.interval.as_secs()),
+        );
+        obj.insert("next_run".to_string(), Value::from(rfc3339(&self.next_run)));
+        Value::Object(obj)
+    }
+}
+
+impl fmt::Display for TimerInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, ", and it will {} in {:?}", self.action, self.interval)
+    }
+}
+
+/// Result of one command which succeeded
+#[derive(Debug, Clone)]
+pub struct Response {
+    pub op: String,
+    pub child: Option<String>,
+
+    /// human readable result, like "restart child1 success"
+    pub message: String,
+
+    /// timer started by this command
+    pub timer: Option<TimerInfo>,
+
+    /// children this command touched or shows, with their status
+    pub children: Vec<(String, Status)>,
+}
+
+impl Response {
+    pub fn new(command: &Command, message: String) -> Self {
+        Response {
+            op: command.get_ops().to_string(),
+            child: command.child_name.clone(),
+            message,
+            timer: None,
+            children: vec![],
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        let mut obj = Map::new();
+        obj.insert("status".to_string(), Value::from("ok"));
+        obj.insert("op".to_string(), Value::from(self.op.as_str()));
+        obj.insert("child".to_string(), opt_str(self.child.as_deref()));
+        obj.insert("message".to_string(), Value::from(self.to_string()));
+        obj.insert(
+            "timer".to_string(),
+            self.timer.as_ref().map_or(Value::Null, |t| t.to_json()),
+        );
+        obj.insert(
+            "children".to_string(),
+            Value::Array(
+                self.children
+                    .iter()
+                    .map(|(name, status)| child_json(name, status))
+                    .collect(),
+            ),
+        );
+        Value::Object(obj)
+    }
+
+    /// render in format client asks
+    pub fn render(&self, format: OutputFormat) -> String {
+        match format {
+            OutputFormat::Text => self.to_string(),
+            OutputFormat::Json => self.to_json().to_string(),
+        }
+    }
+}
+
+/// text rendering, message with timer
+impl fmt::Display for Response {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+        if let Some(t) = &self.timer {
+            write!(f, "{}", t)?;
+        }
+        Ok(())
+    }
+}
+
+/// json of failed command, command is None if it cannot be parsed
+pub fn error_json(command: Option<&Command>, e: &ioError) -> Value {
+    let mut obj = Map::new();
+    obj.insert("status".to_string(), Value::from("error"));
+    obj.insert(
+        "op".to_string(),
+        command.map_or(Value::Null, |c| Value::from(c.get_ops().to_string())),
+    );
+    obj.insert(
+        "child".to_string(),
+        opt_str(command.and_then(|c| c.child_name.as_deref())),
+    );
+
+    let mut error = Map::new();
+    error.insert("kind".to_string(), Value::from(error_kind(e)));
+    error.insert("message".to_string(), Value::from(e.to_string()));
+    obj.insert("error".to_string(), Value::Object(error));
+    Value::Object(obj)
+}
+
+/// render error in format client asks
+pub fn render_error(command: Option<&Command>, e: &ioError) -> String {
+    match command.map(|c| c.output_format()) {
+        Some(OutputFormat::Json) => error_json(command, e).to_string(),
+        _ => e.to_string(),
+    }
+}
+
+/// ErrorKind in snake case, like "not_found". "access_denied" of key_acl
+fn error_kind(e: &ioError) -> String {
+    if acl::is_denied(e) {
+        return "access_denied".to_string();
+    }
+
+    let mut kind = String::new();
+    for (i, c) in format!("{:?}", e.kind()).chars().enumerate() {
+        if c.is_uppercase() && i != 0 {
+            kind.push('_');
+        }
+        kind.push(c.to_ascii_lowercase());
+    }
+    kind
+}
+
+fn child_json(name: &str, status: &Status) -> Value {
+    let mut obj = Map::new();
+    obj.insert("name".to_string(), Value::from(name));
+    obj.insert(
+        "state".to_string(),
+        Value::from(status.state.to_string().to_lowercase()),
+    );
+    obj.insert(
+        "pid".to_string(),
+        status.pid.map_or(Value::Null, Value::from),
+    );
+    obj.insert(
+        "start_time".to_string(),
+        status
+            .start_time
+            .map_or(Value::Null, |t| Value::from(rfc3339(&t))),
+    );
+    obj.insert(
+        "uptime_secs".to_string(),
+        status.start_time.map_or(Value::Null, |t| {
+            Value::from((Local::now() - t).num_seconds().max(0))
+        }),
+    );
+    obj.insert(
+        "exit_code".to_string(),
+        match status.state {
+            ChildState::Exited(Some(c)) => Value::from(c),
+            _ => Value::Null,
+        },
+    );
+    obj.insert(
+        "last_exit".to_string(),
+        opt_str(status.last_exit.as_deref()),
+    );
+    obj.insert(
+        "config_changed".to_string(),
+        opt_str(status.config_changed.as_deref()),
+    );
+    Value::Object(obj)
+}
+
+fn opt_str(s: Option<&str>) -> Value {
+    s.map(Value::from).unwrap_or(Value::Null)
+}
+
+fn rfc3339(t: &DateTime<Local>) -> String {
+    t.to_rfc3339_opts(SecondsFormat::Secs, false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::ErrorKind;
+
+    #[test]
+    fn test_response_json() {
+        let command = Command::new_from_str(vec!["restart", "child1", "--output", "json"]).unwrap();
+        assert_eq!(command.output_format(), OutputFormat::Json);
+
+        let mut status = Status::new();
+        status.started(1234, Local::now());
+        let mut resp = Response::new(&command, "restart child1 success".to_string());
+        resp.timer = Some(TimerInfo::new("restart", Duration::from_secs(5)));
+        resp.children = vec![("child1".to_string(), status)];
+
+        assert_eq!(
+            resp.to_string(),
+            "restart child1 success, and it will restart in 5s"
+        );
+
+        let json: Value = serde_json::from_str(&resp.render(OutputFormat::Json)).unwrap();
+        assert_eq!(json["status"], "ok");
+        assert_eq!(json["op"], "restart");
+        assert_eq!(json["child"], "child1");
+        assert_eq!(json["timer"]["interval_secs"], 5);
+        assert_eq!(json["children"][0]["pid"], 1234);
+        assert_eq!(json["children"][0]["state"], "starting");
+
+        let err: Value = serde_json::from_str(&render_error(
+            Some(&command),
+            &ioError::new(
+                ErrorKind::NotFound,
+                "Cannot found 'child1' file in load path",
+            ),
+        ))
+        .unwrap();
+        assert_eq!(err["status"], "error");
+        assert_eq!(err["error"]["kind"], "not_found");
+        assert_eq!(
+            err["error"]["message"],
+            "Cannot found 'child1' file in load path"
+        );
+    }
+}
@@ -1,24 +1,44 @@
-use super::child::{child_output::OutputMode, Config};
+use super::acl::{Acl, ChildScope};
+use super::audit;
+use super::child::{
+    child_history::{Event, DEFAULT_HISTORY_SIZE},
+    child_log::{pipe_to, LinePrefixer, LogFile, OutputBuffer, Sink, Stream},
+    child_output::{Output, OutputTarget},
+    child_state::{format_table, status_table},
+    child_syslog::JournalWriter,
+    Config,
+};
 use super::client;
+use super::http;
 use super::keys_handler::*;
 use super::kindergarten::*;
 use super::logger;
+use super::protocol;
+use super::response::{self, Response, TimerInfo};
 use super::timer::*;
+use super::tls::{self, TlsConfig};
+use super::unix_socket::UnixSocketConfig;
 
 use chrono::prelude::*;
-use openssl::rsa::*;
+use openssl::pkey::{PKey, Public};
+use openssl::ssl::{SslAcceptor, SslStream};
 use std::collections::HashSet;
 use std::fs;
-use std::fs::{File, OpenOptions};
-use std::io::{Error as ioError, ErrorKind, Read, Result, Write};
-use std::net::{TcpListener, TcpStream};
-use std::process::{Child, Command};
-use std::sync::mpsc::Sender;
-use std::thread;
-use yaml_rust::YamlLoader;
+use std::fs::File;
+use std::io::{self, Error as ioError, ErrorKind, Read, Result, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{RecvTimeoutError, Sender};
+use std::{thread, time};
+use yaml_rust::{Yaml, YamlLoader};
 
 use std::sync::{Arc, Mutex};
 
+/// default lines number of "logs" command
+const DEFAULT_LOGS_LINES: usize = 100;
+
 /// Server config
 #[derive(Debug)]
 struct ServerConfig {
@@ -34,16 +54,72 @@ struct ServerConfig {
     /// The list of children want to start up with server
     startup_list: Option<Vec<String>>,
 
-    /// encrypt mode
+    /// encrypt mode, "on" (key pairs), "hmac" (shared secret) or "off"
     encrypt_mode: String,
 
     /// client public keys location
     keys_path: Option<Vec<String>>,
 
+    /// file of shared secret in hmac mode
+    hmac_secret: Option<String>,
+
     /// Listener address
     /// default is 0.0.0.0, ipv4
     listener_addr: String,
 
+    /// Listener port, default is 33889
+    listener_port: u16,
+
+    /// Size of each child's output buffer in memory, KB
+    log_buffer_size: usize,
+
+    /// How many lifecycle events kept for each child
+    history_size: usize,
+
+    /// server log level: error, warn, info (default) or debug
+    log_level: logger::Level,
+
+    /// server log format: text (default) or json
+    log_format: logger::Format,
+
+    /// server log file, default is stdout
+    log_file: Option<String>,
+
+    /// audit log of client commands, off if not given
+    audit_log: Option<Output>,
+
+    /// listen on TCP or not, default is on. Off only when unix_socket is given
+    tcp: bool,
+
+    /// unix socket listener for local clients
+    unix_socket: Option<UnixSocketConfig>,
+
+    /// TLS of TCP listener, plain TCP if not given
+    tls: Option<TlsConfig>,
+
+    /// accept old "keyname;encrypted command" of encrypt mode, it can be replayed.
+    /// default is off
+    legacy_signature: bool,
+
+    /// seconds signed command is valid for
+    signature_max_age: u64,
+
+    /// other addresses clients may sign commands for, like address before NAT.
+    /// address client connected to is always accepted
+    server_names: Vec<String>,
+
+    /// ops and children each key can use in encrypt mode, all keys can do anything if None
+    key_acl: Option<Acl>,
+
+    /// key names or fingerprints refused in encrypt mode, even if key is in keys path
+    revoked_keys: Vec<String>,
+
+    /// HTTP API address, default is listener_addr
+    http_addr: String,
+
+    /// HTTP API port, HTTP API is off if not given
+    http_port: Option<u16>,
+
     /// Enable ipv6 or not, only used when listener_addr
     /// isn't given in yaml file. If listener_addr appears in yaml,
     /// this field doesn't matter.
@@ -74,8 +150,26 @@ impl ServerConfig {
 
             encrypt_mode: "off".to_string(),
             keys_path: None,
+            hmac_secret: None,
 
             listener_addr: "0.0.0.0".to_string(),
+            listener_port: client::DEFAULT_PORT,
+            log_buffer_size: DEFAULT_LOG_BUFFER_SIZE / 1024,
+            history_size: DEFAULT_HISTORY_SIZE,
+            log_level: logger::Level::Info,
+            log_format: logger::Format::Text,
+            log_file: None,
+            audit_log: None,
+            tcp: true,
+            unix_socket: None,
+            tls: None,
+            legacy_signature: false,
+            signature_max_age: DEFAULT_SIGNATURE_MAX_AGE,
+            server_names: vec![],
+            key_acl: None,
+            revoked_keys: vec![],
+            http_addr: "0.0.0.0".to_string(),
+            http_port: None,
             ipv6: false,
         };
 
@@ -118,6 +212,15 @@ impl ServerConfig {
                 };
                 result.encrypt_mode = encrypt;
 
+                // shared secret of hmac mode, read when command comes
+                result.hmac_secret = doc["hmac_secret"].as_str().map(|s| s.to_string());
+                if result.encrypt_mode == "hmac" && result.hmac_secret.is_none() {
+                    return Err(ioError::new(
+                        ErrorKind::InvalidData,
+                        "encrypt: hmac needs hmac_secret field",
+                    ));
+                }
+
                 // keys path parse
                 let keys_paths = match doc["pub_keys_path"].as_vec() {
                     Some(v) => Some(
@@ -148,6 +251,86 @@ impl ServerConfig {
                     }
                 };
                 result.listener_addr = listener_addr;
+                if let Some(port) = port_field(&doc["listener_port"], "listener_port")? {
+                    result.listener_port = port;
+                }
+
+                // unix socket, with or instead of TCP
+                if !doc["unix_socket"].is_badvalue() {
+                    result.unix_socket = Some(UnixSocketConfig::new(&doc["unix_socket"])?);
+                }
+                if let Some("off") = doc["tcp"].as_str() {
+                    if result.unix_socket.is_none() {
+                        return Err(ioError::new(
+                            ErrorKind::InvalidInput,
+                            "tcp is off, unix_socket has to be given",
+                        ));
+                    }
+                    result.tcp = false;
+                }
+
+                // TLS of TCP listener
+                if !doc["tls"].is_badvalue() {
+                    result.tls = Some(TlsConfig::new(&doc["tls"])?);
+                }
+
+                // signed commands of encrypt mode
+                result.legacy_signature = doc["legacy_signature"].as_str() == Some("on");
+                if let Some(age) = doc["signature_max_age"].as_i64() {
+                    if age > 0 {
+                        result.signature_max_age = age as u64;
+                    }
+                }
+                if let Some(names) = doc["server_names"].as_vec() {
+                    result.server_names = names
+                        .iter()
+                        .filter_map(|n| n.as_str().map(|s| s.to_string()))
+                        .collect();
+                }
+                if !doc["key_acl"].is_badvalue() {
+                    result.key_acl = Some(Acl::new(&doc["key_acl"])?);
+                }
+                if let Some(keys) = doc["revoked_keys"].as_vec() {
+                    result.revoked_keys = keys
+                        .iter()
+                        .filter_map(|k| k.as_str().map(|s| s.to_string()))
+                        .collect();
+                }
+
+                // HTTP API
+                result.http_addr = match doc["http_addr"].as_str() {
+                    Some(addr) => addr.to_string(),
+                    None => result.listener_addr.clone(),
+                };
+                result.http_port = port_field(&doc["http_port"], "http_port")?;
+
+                // output buffer size
+                if let Some(size) = doc["log_buffer_size"].as_i64() {
+                    if size > 0 {
+                        result.log_buffer_size = size as usize;
+                    }
+                }
+
+                // history size
+                if let Some(size) = doc["history_size"].as_i64() {
+                    if size >= 0 {
+                        result.history_size = size as usize;
+                    }
+                }
+
+                // server log
+                if let Some(level) = doc["log_level"].as_str() {
+                    result.log_level = level.parse()?;
+                }
+                if let Some(format) = doc["log_format"].as_str() {
+                    result.log_format = format.parse()?;
+                }
+                result.log_file = doc["log_file"].as_str().map(|s| s.to_string());
+
+                // audit log
+                if !doc["audit_log"].is_badvalue() {
+                    result.audit_log = Some(Output::new_log_file(&doc["audit_log"])?);
+                }
             }
             Err(e) => return Err(ioError::new(ErrorKind::Other, e)),
         }
@@ -229,40 +412,102 @@ impl ServerConfig {
         ))
     }
 
-    /// Return key's path
-    fn find_pubkey_by_name(&self, filename: &String) -> Result<String> {
-        if let Some(paths) = &self.keys_path {
-            for path in paths {
-                for entry in fs::read_dir(path)? {
-                    if let Ok(entry) = entry {
-                        if let Some(extension) = entry.path().extension() {
-                            if extension == "pem" {
-                                if entry
-                                    .file_name()
-                                    .to_str()
-                                    .unwrap()
-                                    .split('.')
-                                    .collect::<Vec<&str>>()[0]
-                                    .to_string()
-                                    == *filename
-                                {
-                                    return Ok(entry
-                                        .path()
-                                        .into_os_string()
-                                        .into_string()
-                                        .unwrap());
-                                }
-                            }
-                        }
-                    }
-                }
+    /// signed host is address client connected to, or one of server_names.
+    /// ipv4 address and ipv4-mapped ipv6 address are same
+    fn is_server_name(&self, signed: &str, local: &str) -> bool {
+        let same = |name: &str| match (name.parse::<SocketAddr>(), local.parse::<SocketAddr>()) {
+            (Ok(a), Ok(b)) => {
+                a.ip().to_canonical() == b.ip().to_canonical() && a.port() == b.port()
             }
+            _ => name == local,
+        };
+        same(signed) || self.server_names.iter().any(|n| n == signed)
+    }
+
+    /// public key of signed commands, `.pem` file named by key in keys path,
+    /// or line of authorized_keys file in keys path has key name as comment.
+    /// revoked key is refused before its signature is checked
+    fn find_pubkey(&self, name: &str) -> Result<PKey<Public>> {
+        let revoked = || {
+            ioError::new(
+                ErrorKind::PermissionDenied,
+                format!("key {} is revoked", name),
+            )
+        };
+        if self.is_revoked(name, None) {
+            return Err(revoked());
         }
 
-        Err(ioError::new(
-            ErrorKind::NotFound,
-            format!("Cannot found '{}' file in keys path", filename),
-        ))
+        let key = self
+            .trusted_keys()?
+            .into_iter()
+            .find(|k| k.name == name)
+            .ok_or_else(|| {
+                ioError::new(
+                    ErrorKind::NotFound,
+                    format!(
+                        "Cannot found '{}' file or authorized key in keys path",
+                        name
+                    ),
+                )
+            })?;
+
+        if self.is_revoked(name, Some(&fingerprint(&key.key)?)) {
+            return Err(revoked());
+        }
+        Ok(key.key)
+    }
+
+    /// all keys in keys path, revoked ones too
+    fn trusted_keys(&self) -> Result<Vec<TrustedKey>> {
+        let keys_path = self.keys_path.as_deref().unwrap_or_default();
+        let (keys, broken) = TrustedKey::load_all(keys_path);
+        for (path, e) in broken {
+            // keys directory is missing or cannot be read, keys of other paths still work
+            if keys_path.contains(&path) && !Path::new(&path).is_file() {
+                logger::warn(
+                    "cannot read keys path",
+                    &[("path", &path), ("error", &e.to_string())],
+                );
+            // key refused on purpose, like options of authorized_keys, is worth a warning
+            } else if e.kind() == ErrorKind::PermissionDenied {
+                logger::warn("refuse key", &[("path", &path), ("error", &e.to_string())]);
+            } else {
+                logger::debug(
+                    "skip key cannot be parsed",
+                    &[("path", &path), ("error", &e.to_string())],
+                );
+            }
+        }
+        Ok(keys)
+    }
+
+    /// all clients of hmac mode are key "hmac", other key names are forged by
+    /// someone has the secret, to use key_acl of other keys
+    fn check_hmac_key(&self, key_name: &str) -> Result<()> {
+        if key_name != HMAC_KEY_NAME {
+            return Err(ioError::new(
+                ErrorKind::PermissionDenied,
+                format!(
+                    "key name of hmac mode is {}, not {}",
+                    HMAC_KEY_NAME, key_name
+                ),
+            ));
+        }
+        if self.is_revoked(HMAC_KEY_NAME, None) {
+            return Err(ioError::new(
+                ErrorKind::PermissionDenied,
+                format!("key {} is revoked", HMAC_KEY_NAME),
+            ));
+        }
+        Ok(())
+    }
+
+    /// name or fingerprint of key is in revoked_keys
+    fn is_revoked(&self, name: &str, fingerprint: Option<&str>) -> bool {
+        self.revoked_keys
+            .iter()
+            .any(|r| r == name || Some(r.as_str()) == fingerprint)
     }
 
     /// recursive check if config have dead loop prehook call
@@ -330,9 +575,31 @@ impl ServerConfig {
     }
 }
 
+/// port fields of server config, None if not given. Port is integer 1 to 65535
+fn port_field(input: &Yaml, name: &str) -> Result<Option<u16>> {
+    match input {
+        Yaml::BadValue => Ok(None),
+        Yaml::Integer(port) if (1..=u16::MAX as i64).contains(port) => Ok(Some(*port as u16)),
+        _ => Err(ioError::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "{} {:?} is not a port, it should be integer 1 to 65535",
+                name, input
+            ),
+        )),
+    }
+}
+
 /// Start a child processing, and give child_handle
 /// Side effection: config.child_id be updated
-pub fn start_new_child(config: &mut Config) -> Result<Child> {
+///
+/// A stream of child is piped to server only if server needs to read it: output buffer
+/// of `logs` (`log_buffer_size`), rotation, prefix, syslog or journald. Otherwise child
+/// writes its output file by itself, or server's stdout/stderr if no output given.
+pub fn start_new_child(
+    config: &mut Config,
+    buffer: Option<Arc<Mutex<OutputBuffer>>>,
+) -> Result<Child> {
     let (com, args) = config.split_args();
 
     let mut command = Command::new(&com);
@@ -346,55 +613,158 @@ pub fn start_new_child(config: &mut Config) -> Result<Child> {
         _ => (),
     };
 
-    // setting stdout and stderr file path
-    match &config.stdout {
-        Some(out) => {
-            match out.mode {
-                OutputMode::Create => command.stdout(File::create(out.path.clone())?),
-                OutputMode::Append => command.stdout(
-                    OpenOptions::new()
-                        .append(true)
-                        .create(true)
-                        .open(out.path.clone())?,
-                ),
-            };
-            ()
-        }
-        None => (),
-    }
+    let piped = |out: &Option<Output>| {
+        buffer.is_some()
+            || out
+                .as_ref()
+                .is_some_and(|o| o.target != OutputTarget::File || o.need_rotation() || o.prefix)
+    };
+    let stdout_piped = piped(&config.stdout);
+    let stderr_piped = !config.redirect_stderr && piped(&config.stderr);
 
-    match &config.stderr {
-        Some(err) => {
-            match err.mode {
-                OutputMode::Create => command.stderr(File::create(err.path.clone())?),
-                OutputMode::Append => command.stderr(
-                    OpenOptions::new()
-                        .append(true)
-                        .create(true)
-                        .open(err.path.clone())?,
-                ),
-            };
-            ()
+    // open output files before child starts
+    let mut stdout_log = open_log(&config.stdout)?;
+    let mut stderr_log = if config.redirect_stderr {
+        None
+    } else {
+        open_log(&config.stderr)?
+    };
+
+    // if redirect_stderr, both streams share one pipe (or file), so they keep in order
+    let mut merged_pipe = None;
+    if config.redirect_stderr && stdout_piped {
+        let (reader, writer) = io::pipe()?;
+        command.stderr(writer.try_clone()?);
+        command.stdout(writer);
+        merged_pipe = Some(reader);
+    } else if config.redirect_stderr {
+        match stdout_log.take() {
+            Some(log) => {
+                let file = log.into_file();
+                command.stderr(file.try_clone()?);
+                command.stdout(file);
+            }
+            None => {
+                command.stderr(io::stdout());
+            }
         }
-        None => (),
+    } else {
+        command.stdout(child_stdio(stdout_piped, &mut stdout_log));
+        command.stderr(child_stdio(stderr_piped, &mut stderr_log));
     }
 
     //run command and give child handle
-    let child = command.spawn();
-
-    match child {
-        Ok(ref c) => {
-            config.child_id = Some(c.id());
-            config.start_time = Some(Local::now());
-            return child;
-        }
-        _ => {
+    let mut child = match command.spawn() {
+        Ok(c) => c,
+        Err(_) => {
             return Err(ioError::new(
                 ErrorKind::Other,
                 format!("Cannot start command {:?}", command),
             ));
         }
     };
+    // command holds write end of merged pipe, drop it so pipe can close with child
+    drop(command);
+
+    config.child_id = Some(child.id());
+    config.start_time = Some(Local::now());
+    if let Some(b) = &buffer {
+        b.lock().unwrap().mark_start(child.id());
+    }
+
+    config.log_files.clear();
+    let sinks =
+        output_sinks(config, Stream::Stdout, stdout_piped, stdout_log, &buffer).and_then(|out| {
+            output_sinks(config, Stream::Stderr, stderr_piped, stderr_log, &buffer)
+                .map(|err| (out, err))
+        });
+    let (stdout_sinks, stderr_sinks) = match sinks {
+        Ok(s) => s,
+        Err(e) => {
+            // child cannot output anywhere, do not leave it running
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(e);
+        }
+    };
+
+    if let Some(sinks) = stdout_sinks {
+        match merged_pipe {
+            Some(pipe) => pipe_to(&config.name, pipe, sinks),
+            None => pipe_to(&config.name, child.stdout.take().unwrap(), sinks),
+        };
+    }
+
+    if let (Some(pipe), Some(sinks)) = (child.stderr.take(), stderr_sinks) {
+        pipe_to(&config.name, pipe, sinks);
+    }
+
+    Ok(child)
+}
+
+/// output file of stream, None if it goes to server's stdout/stderr, syslog or journald
+fn open_log(output: &Option<Output>) -> Result<Option<LogFile>> {
+    match output {
+        Some(out) if out.target == OutputTarget::File => LogFile::open(out).map(Some),
+        _ => Ok(None),
+    }
+}
+
+/// stdio of stream not merged: pipe, output file child writes by itself, or inherit
+fn child_stdio(piped: bool, log: &mut Option<LogFile>) -> Stdio {
+    match log.take() {
+        _ if piped => Stdio::piped(),
+        Some(log) => Stdio::from(log.into_file()),
+        None => Stdio::inherit(),
+    }
+}
+
+/// Make sinks of one piped stream of child, None if it is not piped. Output goes to
+/// buffer if it has, then goes to log file, syslog or journald, or server's stdout/stderr
+fn output_sinks(
+    config: &mut Config,
+    stream: Stream,
+    piped: bool,
+    log: Option<LogFile>,
+    buffer: &Option<Arc<Mutex<OutputBuffer>>>,
+) -> Result<Option<Vec<Sink>>> {
+    if !piped {
+        return Ok(None);
+    }
+
+    let mut sinks = vec![];
+    if let Some(b) = buffer {
+        sinks.push(Sink::Buffer(Arc::clone(b), stream));
+    }
+
+    let output = match stream {
+        Stream::Stdout => config.stdout.as_ref(),
+        Stream::Stderr => config.stderr.as_ref(),
+    };
+
+    match (output, log) {
+        (Some(out), Some(log)) => {
+            let prefixer = if out.prefix {
+                Some(LinePrefixer::new(&config.name, stream))
+            } else {
+                None
+            };
+            let log = Arc::new(Mutex::new(log));
+            config.log_files.push(Arc::clone(&log));
+            sinks.push(Sink::File(log, prefixer));
+        }
+        (Some(out), None) if out.target != OutputTarget::File => {
+            let writer =
+                JournalWriter::new(out, &config.name, stream, config.child_id.unwrap_or(0))?;
+            sinks.push(Sink::Journal(writer, LinePrefixer::plain()));
+        }
+        _ => sinks.push(match stream {
+            Stream::Stdout => Sink::ServerStdout,
+            Stream::Stderr => Sink::ServerStderr,
+        }),
+    }
+
+    Ok(Some(sinks))
 }
 
 /// Check if child name is legal or not
@@ -428,6 +798,16 @@ pub fn start_new_server(config_path: &str) -> Result<Kindergarten> {
         ServerConfig::load(config_path)?
     };
 
+    logger::init(
+        server_conf.log_level,
+        server_conf.log_format,
+        server_conf.log_file.as_deref(),
+    )?;
+
+    if let Some(output) = &server_conf.audit_log {
+        audit::init(output)?;
+    }
+
     // create new kindergarten
     let mut kindergarten = Kindergarten::new();
 
@@ -435,8 +815,11 @@ pub fn start_new_server(config_path: &str) -> Result<Kindergarten> {
     // after this, server_config_path should never changed
     kindergarten.server_config_path = config_path.to_string();
 
-    // kindergarden make encrypt on
-    if server_conf.encrypt_mode == "on" {
+    kindergarten.log_buffer_size = server_conf.log_buffer_size * 1024;
+    kindergarten.history_size = server_conf.history_size;
+
+    // kindergarden make encrypt on, commands have to be signed by key or secret
+    if server_conf.encrypt_mode == "on" || server_conf.encrypt_mode == "hmac" {
         kindergarten.encrypt_mode = true;
     }
 
@@ -449,15 +832,16 @@ pub fn start_new_server(config_path: &str) -> Result<Kindergarten> {
 
     // print log
     if startup_children.len() != 0 {
-        println!(
-            "{}",
-            logger::timelog(&format!(
-                "these children will start with server startup: {:?}",
-                startup_children
+        logger::info(
+            "these children will start with server startup",
+            &[(
+                "children",
+                &startup_children
                     .iter()
                     .map(|x| x.1.clone())
                     .collect::<Vec<String>>()
-            ))
+                    .join(","),
+            )],
         );
     }
 
@@ -468,43 +852,51 @@ pub fn start_new_server(config_path: &str) -> Result<Kindergarten> {
         // ..., however, server un-quiet mode won't through client command check.
         // so we need check again here.
         if let Err(e) = child_name_legal_check(&conf.0) {
-            println!("{}", logger::timelog(&e));
+            logger::warn(&e, &[("child", &conf.0)]);
             continue;
         };
 
         let mut child_config = Config::read_from_yaml_file((&conf.1).into())?;
 
-        let child_handle = start_new_child(&mut child_config)?;
+        let child_handle = start_new_child(&mut child_config, kindergarten.output_buffer(&conf.0))?;
 
-        println!("{}", logger::timelog(&format!("start {} success", conf.0)));
+        logger::info(
+            "start child success",
+            &[("child", &conf.0), ("pid", &child_handle.id().to_string())],
+        );
 
         // because repeat function need kindergarden be created.
         if child_config.is_repeat() {
-            println!(
-                "{}",
-                logger::timelog(&format!(
-                    "find child {} has repeat status, not support repeat in un-quiet mode during server startup",
-                    &conf.0
-                ))
+            logger::warn(
+                "child has repeat status, not support repeat in un-quiet mode during server startup",
+                &[("child", &conf.0)],
             )
         };
 
         // because repeat function need kindergarden be created.
         if child_config.has_hook() {
-            println!(
-                "{}",
-                logger::timelog(&format!(
-                    "find child {} has hook(s), not support prehook in un-quiet mode during server startup",
-                    &conf.0
-                ))
+            logger::warn(
+                "child has hook(s), not support prehook in un-quiet mode during server startup",
+                &[("child", &conf.0)],
             )
         };
 
         // registe id
         let id = child_config.child_id.unwrap();
+        let child_config_start_time = child_config.start_time.unwrap_or_else(Local::now);
         kindergarten.register_id(id, child_handle, child_config);
         // regist name
         kindergarten.register_name(&conf.0, id);
+        kindergarten
+            .status_mut(&conf.0)
+            .started(id, child_config_start_time);
+        kindergarten.record(
+            &conf.0,
+            Event::Started {
+                pid: id,
+                by: "server startup".to_string(),
+            },
+        );
     }
 
     Ok(kindergarten)
@@ -521,133 +913,745 @@ pub fn start_deamon(safe_kg: Arc<Mutex<Kindergarten>>, sd: Sender<(String, Strin
     };
 
     // start TCP listener to receive client commands
-    let listener = TcpListener::bind((server_conf.listener_addr.clone(), 33889)).unwrap();
-    println!(
-        "{} {}:{}",
-        logger::timelog("Server is listening on"),
-        server_conf.listener_addr,
-        33889
-    );
+    let tcp_listener = if server_conf.tcp {
+        let addr = format!(
+            "{}:{}",
+            server_conf.listener_addr, server_conf.listener_port
+        );
+        let listener =
+            TcpListener::bind((server_conf.listener_addr.clone(), server_conf.listener_port))?;
+        logger::info("Server is listening", &[("addr", &addr)]);
+        Some(listener)
+    } else {
+        None
+    };
+
+    // handshake of each client is done in its own thread
+    let tls_acceptor = match &server_conf.tls {
+        Some(conf) => Some(conf.acceptor()?),
+        None => None,
+    };
+
+    // unix socket listener for local clients
+    let unix_listener = match &server_conf.unix_socket {
+        Some(unix) => {
+            let listener = unix.bind()?;
+            logger::info(
+                "Server is listening",
+                &[("addr", &format!("unix://{}", unix.path))],
+            );
+            Some(listener)
+        }
+        None => None,
+    };
+
+    // HTTP API runs beside
+    if let Some(port) = server_conf.http_port {
+        let http_addr = format!("{}:{}", server_conf.http_addr, port);
+        match TcpListener::bind((server_conf.http_addr.clone(), port)) {
+            Ok(http_listener) => {
+                logger::info("HTTP API is listening", &[("addr", &http_addr)]);
+                let http_kg = Arc::clone(&safe_kg);
+                let http_sd = Sender::clone(&sd);
+                thread::spawn(move || {
+                    serve_connections(http_listener.incoming(), http_kg, http_sd, handle_http)
+                });
+            }
+            Err(e) => logger::error(
+                "cannot start HTTP API",
+                &[("addr", &http_addr), ("error", &e.to_string())],
+            ),
+        }
+    }
+
+    match (tcp_listener, unix_listener) {
+        (Some(tcp), Some(unix)) => {
+            let unix_kg = Arc::clone(&safe_kg);
+            let unix_sd = Sender::clone(&sd);
+            thread::spawn(move || {
+                serve_connections(unix.incoming(), unix_kg, unix_sd, handle_client)
+            });
+            serve_tcp(tcp, tls_acceptor, safe_kg, sd);
+        }
+        (Some(tcp), None) => serve_tcp(tcp, tls_acceptor, safe_kg, sd),
+        (None, Some(unix)) => serve_connections(unix.incoming(), safe_kg, sd, handle_client),
+        (None, None) => {}
+    }
+    Ok(())
+}
+
+/// TCP clients, in TLS if server has tls config
+fn serve_tcp(
+    listener: TcpListener,
+    tls_acceptor: Option<SslAcceptor>,
+    safe_kg: Arc<Mutex<Kindergarten>>,
+    sd: Sender<(String, String)>,
+) {
+    match tls_acceptor {
+        Some(acceptor) => serve_connections(listener.incoming(), safe_kg, sd, move |stream, kg| {
+            handle_tls(&acceptor, stream, kg)
+        }),
+        None => serve_connections(listener.incoming(), safe_kg, sd, handle_client),
+    }
+}
+
+/// stream of client, TCP or unix socket
+trait ClientStream: Read + Write + Send + Sized + 'static {
+    /// who is on the other side, for log and audit
+    fn peer(&self) -> String;
+
+    /// transport name in audit log
+    fn transport(&self) -> &'static str;
+
+    /// protocol handshake, legacy clients only come from TCP
+    fn accept(&mut self) -> Result<protocol::Channel<&mut Self>>;
+
+    fn set_nonblocking(&self, nonblocking: bool) -> Result<()>;
+
+    /// address client connected to, signed commands have to be signed for it
+    fn host(&self) -> String;
+
+    /// name in verified client certificate, it authenticates client like key pairs
+    fn identity(&self) -> Option<String> {
+        None
+    }
+}
+
+impl ClientStream for TcpStream {
+    fn peer(&self) -> String {
+        self.peer_addr().map(|a| a.to_string()).unwrap_or_default()
+    }
+
+    fn transport(&self) -> &'static str {
+        "tcp"
+    }
+
+    fn accept(&mut self) -> Result<protocol::Channel<&mut Self>> {
+        protocol::accept(self)
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> Result<()> {
+        TcpStream::set_nonblocking(self, nonblocking)
+    }
+
+    fn host(&self) -> String {
+        self.local_addr().map(|a| a.to_string()).unwrap_or_default()
+    }
+}
+
+impl ClientStream for UnixStream {
+    /// client of unix socket has no address, use socket path
+    fn peer(&self) -> String {
+        self.local_addr()
+            .ok()
+            .and_then(|a| a.as_pathname().map(|p| format!("unix://{}", p.display())))
+            .unwrap_or_else(|| "unix".to_string())
+    }
+
+    fn transport(&self) -> &'static str {
+        "unix"
+    }
+
+    fn accept(&mut self) -> Result<protocol::Channel<&mut Self>> {
+        protocol::accept_framed(self)
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> Result<()> {
+        UnixStream::set_nonblocking(self, nonblocking)
+    }
+
+    fn host(&self) -> String {
+        self.peer()
+    }
+}
+
+impl ClientStream for SslStream<TcpStream> {
+    fn peer(&self) -> String {
+        self.get_ref().peer()
+    }
+
+    fn transport(&self) -> &'static str {
+        "tls"
+    }
+
+    /// TLS clients are new, no legacy protocol
+    fn accept(&mut self) -> Result<protocol::Channel<&mut Self>> {
+        protocol::accept_framed(self)
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> Result<()> {
+        self.get_ref().set_nonblocking(nonblocking)
+    }
+
+    fn host(&self) -> String {
+        self.get_ref().host()
+    }
+
+    fn identity(&self) -> Option<String> {
+        tls::peer_name(self.ssl())
+    }
+}
 
-    for stream in listener.incoming() {
+/// run handler of each connection in its own thread, tell main thread if server is killed
+fn serve_connections<S, H>(
+    incoming: impl Iterator<Item = Result<S>>,
+    safe_kg: Arc<Mutex<Kindergarten>>,
+    sd: Sender<(String, String)>,
+    handler: H,
+) where
+    S: ClientStream,
+    H: Fn(S, Arc<Mutex<Kindergarten>>) -> Result<String> + Clone + Send + 'static,
+{
+    for stream in incoming {
         match stream {
             Ok(stream) => {
                 let this_kg = Arc::clone(&safe_kg);
                 let sd_ = Sender::clone(&sd);
+                let client_addr = stream.peer();
+                let handler = handler.clone();
                 let _ = thread::spawn(move || {
-                    //run handle_client and catch error if has
-                    match handle_client(stream, this_kg) {
+                    //run handler and catch error if has
+                    match handler(stream, this_kg) {
                         Err(e) => {
                             // hard check if it is suicide operation, because I only care this message so far.
                             // this operation isn't in handle_client because it has make sure return to client..
                             // ..first
                             let (first, second) = {
                                 let s = e.to_string();
-                                let (f, ss) = s.split_at(12);
+                                let (f, ss) = s.split_at(12.min(s.len()));
                                 (f.to_string(), ss.to_string())
                             };
                             if first == "I am dying. " {
-                                println!("{}", logger::timelog(&second));
+                                logger::warn(
+                                    "server is killed by client",
+                                    &[("client", &client_addr), ("last_words", second.trim())],
+                                );
                                 //tell main thread,
                                 sd_.send((first.to_string(), second.to_string())).unwrap();
                             } else {
                                 //if just normal error
-                                println!("{}", logger::timelog(&e.to_string()));
+                                logger::error(
+                                    "command failed",
+                                    &[("client", &client_addr), ("error", &e.to_string())],
+                                );
                             }
                         }
-                        Ok(des) => println!("{}", logger::timelog(&des)),
+                        Ok(des) => logger::info(
+                            "command success",
+                            &[("client", &client_addr), ("response", des.trim())],
+                        ),
                     }
                 });
             }
 
-            Err(e) => println!("{}", logger::timelog(&e.to_string())),
+            Err(e) => logger::error("connection failed", &[("error", &e.to_string())]),
         }
     }
+}
 
-    Ok(())
+/// get client stream and send to channel, every request goes to audit log
+fn handle_client<S: ClientStream>(mut stream: S, kig: Arc<Mutex<Kindergarten>>) -> Result<String> {
+    let client_addr = stream.peer();
+
+    let mut record = audit::Record::new(&client_addr);
+    record.transport = stream.transport();
+    let result = serve_client(&mut stream, kig, &mut record);
+    write_audit(&record, &result);
+
+    result
+}
+
+/// TLS handshake, then serve client like others. failed handshake goes to audit log too
+fn handle_tls(
+    acceptor: &SslAcceptor,
+    stream: TcpStream,
+    kig: Arc<Mutex<Kindergarten>>,
+) -> Result<String> {
+    let client_addr = stream
+        .peer_addr()
+        .map(|a| a.to_string())
+        .unwrap_or_default();
+
+    match TlsConfig::accept(acceptor, stream) {
+        Ok(s) => handle_client(s, kig),
+        Err(e) => {
+            let mut record = audit::Record::new(&client_addr);
+            record.transport = "tls";
+            let result = Err(e);
+            write_audit(&record, &result);
+            result
+        }
+    }
 }
 
-/// get client TCP stream and send to channel
-fn handle_client(mut stream: TcpStream, kig: Arc<Mutex<Kindergarten>>) -> Result<String> {
-    let mut buf = [0; 100 + 4096]; // 100 command length + 4096 key length buffer
-    stream.read(&mut buf)?;
+/// write audit log, failure only goes to server log
+fn write_audit(record: &audit::Record, result: &Result<String>) {
+    if let Err(e) = audit::write(record, result) {
+        logger::error(
+            "cannot write audit log",
+            &[("client", &record.client), ("error", &e.to_string())],
+        );
+    }
+}
+
+/// get HTTP request, every request goes to audit log
+fn handle_http(mut stream: TcpStream, kig: Arc<Mutex<Kindergarten>>) -> Result<String> {
+    let client_addr = stream
+        .peer_addr()
+        .map(|a| a.to_string())
+        .unwrap_or_default();
+
+    let mut record = audit::Record::new(&client_addr);
+    record.transport = "http";
+    let result = serve_http(&mut stream, kig, &mut record);
+    write_audit(&record, &result);
 
-    let buf_vec = {
-        let mut temp = buf.to_vec();
-        temp.reverse();
-        while temp[0] == 0 {
-            temp.drain(..1);
+    result
+}
+
+/// map HTTP request to command, check it like TCP command, then run it
+fn serve_http(
+    stream: &mut TcpStream,
+    kig: Arc<Mutex<Kindergarten>>,
+    record: &mut audit::Record,
+) -> Result<String> {
+    let received_comm = match http_command(stream, &kig, record) {
+        Ok(c) => c,
+        Err(e) => {
+            let body = response::error_json(None, &e).to_string();
+            http::write_response(stream, http::status_code(&e), &body)?;
+            return Err(e);
         }
-        temp.reverse();
-        temp
     };
 
-    // here to check if this command with
-    let received_comm = if kig.lock().unwrap().encrypt_mode {
-        // tell client this server running in encrypt mode
-        stream.write_all("Running on encrypt mode, need to dencrypt your command.\n".as_bytes())?;
+    logger::info(
+        "receive command",
+        &[
+            ("client", &record.client),
+            ("key", record.key.as_deref().unwrap_or_default()),
+            ("command", &received_comm),
+            ("transport", "http"),
+        ],
+    );
+    record.command = Some(received_comm.clone());
+    let operator = match &record.key {
+        Some(key) => format!("http client {} with key {}", record.client, key),
+        None => format!("http client {}", record.client),
+    };
 
-        // re-read server config
-        let server_conf = if kig.lock().unwrap().server_config_path == "" {
-            ServerConfig::load("/tmp/server.yml")?
-        } else {
-            ServerConfig::load(&kig.lock().unwrap().server_config_path)?
-        };
+    let command =
+        client::Command::new_from_str(received_comm.split(' ').collect::<Vec<&str>>()).ok();
+    let scope = match check_acl(&kig, record, command.as_ref()) {
+        Ok(s) => s,
+        Err(e) => {
+            let body = response::error_json(command.as_ref(), &e).to_string();
+            http::write_response(stream, http::status_code(&e), &body)?;
+            return Err(e);
+        }
+    };
+    match day_care(
+        kig,
+        format!("{} --output json", received_comm),
+        &operator,
+        &scope,
+    ) {
+        Ok(resp) => {
+            http::write_response(stream, 200, &resp.to_json().to_string())?;
+            Ok(resp.to_string())
+        }
+        // shutdown is not failed, but let deamon know it by error
+        Err(e) if received_comm == "kill" => {
+            let body = serde_json::json!({"status": "ok", "op": "kill", "message": e.to_string()});
+            http::write_response(stream, 200, &body.to_string())?;
+            Err(e)
+        }
+        Err(e) => {
+            let body = response::error_json(command.as_ref(), &e).to_string();
+            http::write_response(stream, http::status_code(&e), &body)?;
+            Err(e)
+        }
+    }
+}
 
-        // parse keyname and encrypted data
-        let (keyname, data) = match DataWrapper::unwrap_from(&buf_vec) {
-            Ok((n, d)) => (n, d),
-            Err(e) => {
-                stream.write_all(e.to_string().as_bytes())?;
-                return Err(e);
-            }
-        };
+/// command of HTTP request, in encrypt mode it has to be signed by client key
+fn http_command(
+    stream: &mut TcpStream,
+    kig: &Arc<Mutex<Kindergarten>>,
+    record: &mut audit::Record,
+) -> Result<String> {
+    let request = http::Request::read_from(&mut io::BufReader::new(&*stream))?;
+    let command = http::route(&request.method, &request.path)?;
+
+    if kig.lock().unwrap().encrypt_mode {
+        request.refuse_read()?;
+        let host = stream
+            .local_addr()
+            .map(|a| a.to_string())
+            .unwrap_or_default();
+        let (signed, _) = decrypt_command(kig, &request.auth_token()?, &host, record)
+            .map_err(|e| ioError::new(ErrorKind::PermissionDenied, e.to_string()))?;
+        if signed != command {
+            return Err(ioError::new(
+                ErrorKind::PermissionDenied,
+                format!("signed command '{}' is not '{}'", signed, command),
+            ));
+        }
+    }
 
-        let key = {
-            let k_path = match server_conf.find_pubkey_by_name(&keyname) {
-                Ok(kk) => kk,
-                Err(e) => {
-                    stream.write_all(e.to_string().as_bytes())?;
-                    return Err(e);
-                }
-            };
+    Ok(command)
+}
 
-            let mut content = String::new();
-            let _ = File::open(k_path)?.read_to_string(&mut content);
-            Rsa::public_key_from_pem(&content.as_bytes())?
-        };
+/// re-read server config, keys and their permissions can be changed while running
+fn reload_config(kig: &Arc<Mutex<Kindergarten>>) -> Result<ServerConfig> {
+    let path = kig.lock().unwrap().server_config_path.clone();
+    if path.is_empty() {
+        ServerConfig::load("/tmp/server.yml")
+    } else {
+        ServerConfig::load(&path)
+    }
+}
+
+/// key of command has to be allowed by key_acl of server config, returns children it can see
+fn check_acl(
+    kig: &Arc<Mutex<Kindergarten>>,
+    record: &audit::Record,
+    command: Option<&client::Command>,
+) -> Result<ChildScope> {
+    // no key, or command is wrong and day_care answers it
+    let (key, command) = match (&record.key, command) {
+        (Some(k), Some(c)) => (k, c),
+        _ => return Ok(ChildScope::all()),
+    };
+
+    if let Some(acl) = &reload_config(kig)?.key_acl {
+        if let Err(e) = acl.check(key, command) {
+            logger::warn(
+                "command denied",
+                &[
+                    ("client", &record.client),
+                    ("key", key),
+                    ("command", record.command.as_deref().unwrap_or_default()),
+                ],
+            );
+            return Err(e);
+        }
+        return Ok(acl.scope(key));
+    }
+    Ok(ChildScope::all())
+}
+
+/// read command from client (decrypt if need), then run it
+fn serve_client<S: ClientStream>(
+    stream: &mut S,
+    kig: Arc<Mutex<Kindergarten>>,
+    record: &mut audit::Record,
+) -> Result<String> {
+    let identity = stream.identity();
+    let host = stream.host();
+
+    // framed protocol, or legacy one during transition
+    let mut conn = stream.accept()?;
+    let result = answer_client(&mut conn, kig, record, identity, &host);
+
+    // client knows encrypted response is not cut off
+    if let Err(e) = conn.finish() {
+        logger::debug(
+            "cannot send end of response",
+            &[("client", &record.client), ("error", &e.to_string())],
+        );
+    }
+    result
+}
+
+/// read command of client, check and run it, then send response
+fn answer_client<S: ClientStream>(
+    conn: &mut protocol::Channel<&mut S>,
+    kig: Arc<Mutex<Kindergarten>>,
+    record: &mut audit::Record,
+    identity: Option<String>,
+    host: &str,
+) -> Result<String> {
+    let client_addr = record.client.clone();
+    let buf_vec = conn.read_request()?;
+
+    // tell text client this server running in encrypt mode
+    let mut notice = String::new();
 
-        let dw = match DataWrapper::decrypt_with_pubkey(data, keyname, key) {
-            Ok(d) => d,
+    // here to check if this command with
+    let encrypt_mode = kig.lock().unwrap().encrypt_mode;
+    let received_comm = if let (true, Some(name)) = (encrypt_mode, identity) {
+        // client certificate is verified already, command isn't encrypted
+        record.key = Some(name.clone());
+        if reload_config(&kig)?.is_revoked(&name, None) {
+            let e = ioError::new(
+                ErrorKind::PermissionDenied,
+                format!("key {} is revoked", name),
+            );
+            conn.send(e.to_string().as_bytes())?;
+            return Err(e);
+        }
+        kig.lock().unwrap().key_used(&name);
+        let data =
+            String::from_utf8(buf_vec).map_err(|e| ioError::new(ErrorKind::InvalidInput, e))?;
+        logger::info(
+            "receive command",
+            &[
+                ("client", &client_addr),
+                ("key", record.key.as_deref().unwrap_or_default()),
+                ("command", &data),
+            ],
+        );
+        data
+    } else if encrypt_mode {
+        notice.push_str("Running on encrypt mode, need to dencrypt your command.\n");
+
+        let data = match decrypt_command(&kig, &buf_vec, host, record) {
+            // everything sent from now on is encrypted to client
+            Ok((d, Some(sealer))) => {
+                conn.set_cipher(Box::new(sealer));
+                d
+            }
+            Ok((d, None)) => d,
+            // nothing verified yet, client is told so
             Err(e) => {
-                stream.write_all(e.to_string().as_bytes())?;
+                conn.send_unsealed(format!("{}{}", notice, e).as_bytes())?;
                 return Err(e);
             }
         };
 
-        dw.data
+        logger::info(
+            "receive command",
+            &[
+                ("client", &client_addr),
+                ("key", record.key.as_deref().unwrap_or_default()),
+                ("command", &data),
+            ],
+        );
+        data
     } else {
         match String::from_utf8(buf_vec) {
-            Ok(s) => s,
+            Ok(s) => {
+                logger::info(
+                    "receive command",
+                    &[("client", &client_addr), ("command", &s)],
+                );
+                s
+            }
             Err(e) => return Err(ioError::new(ErrorKind::InvalidInput, e)),
         }
     };
 
-    match day_care(kig, received_comm) {
+    // json request is answered in json
+    let received_comm = if received_comm.trim_start().starts_with('{') {
+        match client::Command::new_from_json(&received_comm) {
+            Ok(c) => String::from_utf8_lossy(&c.as_bytes()).to_string(),
+            Err(e) => {
+                conn.send(response::error_json(None, &e).to_string().as_bytes())?;
+                return Err(e);
+            }
+        }
+    } else {
+        received_comm
+    };
+
+    record.command = Some(received_comm.clone());
+    let operator = match &record.key {
+        Some(key) => format!("client {} with key {}", client_addr, key),
+        None => format!("client {}", client_addr),
+    };
+
+    let command =
+        client::Command::new_from_str(received_comm.split(' ').collect::<Vec<&str>>()).ok();
+    let format = command
+        .as_ref()
+        .map_or(client::OutputFormat::Text, |c| c.output_format());
+    if format == client::OutputFormat::Json {
+        notice.clear();
+    }
+
+    let scope = match check_acl(&kig, record, command.as_ref()) {
+        Ok(s) => s,
+        Err(e) => {
+            conn.send(
+                format!("{}{}", notice, response::render_error(command.as_ref(), &e)).as_bytes(),
+            )?;
+            return Err(e);
+        }
+    };
+
+    // "logs --follow" keeps this connection and streams output to client
+    if let Some(command) = &command {
+        if command.get_ops() == client::Ops::Logs && command.has_flag(&client::Flags::Follow) {
+            conn.send(notice.as_bytes())?;
+            return follow_logs(conn, kig, command);
+        }
+    }
+
+    match day_care(kig, received_comm, &operator, &scope) {
         Ok(resp) => {
-            stream.write_all(resp.as_bytes())?;
-            Ok(resp)
+            conn.send(format!("{}{}", notice, resp.render(format)).as_bytes())?;
+            Ok(resp.to_string())
         }
         Err(e) => {
-            stream.write_all(e.to_string().as_bytes())?;
+            conn.send(
+                format!("{}{}", notice, response::render_error(command.as_ref(), &e)).as_bytes(),
+            )?;
             Err(e)
         }
     }
 }
 
-/// day care is major function of server handle commands
-pub fn day_care(kig: Arc<Mutex<Kindergarten>>, data: String) -> Result<String> {
+/// decrypt command encrypted by client's private key, key name goes to record.
+/// sealer encrypts response if client sends reply key
+fn decrypt_command(
+    kig: &Arc<Mutex<Kindergarten>>,
+    buf: &[u8],
+    host: &str,
+    record: &mut audit::Record,
+) -> Result<(String, Option<ResponseSealer>)> {
+    let server_conf = reload_config(kig)?;
+
+    let hmac_mode = server_conf.encrypt_mode == "hmac";
+    if hmac_mode && !SignedCommand::is_signed(buf) {
+        return Err(ioError::new(
+            ErrorKind::PermissionDenied,
+            "server is in hmac mode, sign command with \"with hmac\"",
+        ));
+    }
+    if SignedCommand::is_signed(buf) || hmac_mode {
+        let signed = SignedCommand::from_bytes(buf)?;
+        record.key = Some(signed.key_name.clone());
+
+        // same secret of all clients, re-read like keys
+        let secret = if hmac_mode {
+            Some(read_secret(
+                server_conf.hmac_secret.as_deref().unwrap_or_default(),
+            )?)
+        } else {
+            None
+        };
+        if let Some(secret) = &secret {
+            server_conf.check_hmac_key(&signed.key_name)?;
+            signed.verify_hmac(secret)?;
+        } else {
+            let key = server_conf.find_pubkey(&signed.key_name)?;
+            signed.verify(&key)?;
+        }
+
+        if !server_conf.is_server_name(&signed.host, host) {
+            return Err(ioError::new(
+                ErrorKind::PermissionDenied,
+                format!("command is signed for {}, not {}", signed.host, host),
+            ));
+        }
+
+        let now = Utc::now().timestamp();
+        signed.check_fresh(now, server_conf.signature_max_age)?;
+        let mut kg = kig.lock().unwrap();
+        kg.nonces
+            .check_and_insert(&signed, now, server_conf.signature_max_age)?;
+        kg.key_used(&signed.key_name);
+
+        let sealer = match &signed.reply_key {
+            Some(k) => Some(ResponseSealer::new(k, secret.as_deref())?),
+            None => None,
+        };
+        return Ok((signed.command, sealer));
+    }
+
+    // parse keyname and encrypted data
+    let (keyname, data) = DataWrapper::unwrap_from(buf)?;
+    record.key = Some(keyname.clone());
+
+    if !server_conf.legacy_signature {
+        return Err(ioError::new(
+            ErrorKind::PermissionDenied,
+            "legacy encrypted command is refused, update client or set legacy_signature: on",
+        ));
+    }
+
+    let key = server_conf.find_pubkey(&keyname)?.rsa()?;
+    let data = DataWrapper::decrypt_with_pubkey(data, keyname.clone(), key)?.data;
+    kig.lock().unwrap().key_used(&keyname);
+
+    Ok((data, None))
+}
+
+/// send output of child to client until client disconnects
+fn follow_logs<S: ClientStream>(
+    conn: &mut protocol::Channel<&mut S>,
+    kig: Arc<Mutex<Kindergarten>>,
+    command: &client::Command,
+) -> Result<String> {
+    let name = command.child_name.clone().unwrap_or_default();
+    let output_stream = if command.has_flag(&client::Flags::Stderr) {
+        Stream::Stderr
+    } else {
+        Stream::Stdout
+    };
+
+    // do not hold kindergarten during following
+    let follow = kig.lock().unwrap().follow(
+        &name,
+        output_stream,
+        command.lines().unwrap_or(DEFAULT_LOGS_LINES),
+    );
+    let (snapshot, rx) = match follow {
+        Ok(f) => f,
+        Err(e) => {
+            conn.send(e.to_string().as_bytes())?;
+            return Err(e);
+        }
+    };
+
+    conn.send(&snapshot)?;
+    loop {
+        match rx.recv_timeout(time::Duration::from_secs(1)) {
+            Ok(data) => {
+                if conn.send(&data).is_err() {
+                    break;
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                if client_closed(*conn.get_mut()) {
+                    break;
+                }
+            }
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+
+    Ok(format!("client stops following output of {}", name))
+}
+
+/// check if client has closed the connection, without blocking.
+/// client sends nothing after its command, so reading is safe
+fn client_closed<S: ClientStream>(stream: &mut S) -> bool {
+    let mut buf = [0; 1];
+    if stream.set_nonblocking(true).is_err() {
+        return true;
+    }
+
+    let closed = match stream.read(&mut buf) {
+        Ok(0) => true,
+        Ok(_) => false,
+        Err(e) => e.kind() != ErrorKind::WouldBlock,
+    };
+
+    stream.set_nonblocking(false).is_err() || closed
+}
+
+/// day care is major function of server handle commands.
+/// operator is who sends this command, recorded in children history
+/// scope limits children shown by ops without child name, by key_acl of client
+pub fn day_care(
+    kig: Arc<Mutex<Kindergarten>>,
+    data: String,
+    operator: &str,
+    scope: &ChildScope,
+) -> Result<Response> {
     let mut kg = kig.lock().unwrap();
+    kg.operator = operator.to_string();
 
     // run check around here, clean all stopped children
     // check operation has its own check_around too,
@@ -662,7 +1666,10 @@ pub fn day_care(kig: Arc<Mutex<Kindergarten>>, data: String) -> Result<String> {
         ServerConfig::load(&kg.server_config_path)?
     };
 
-    match command.get_ops() {
+    // timer started by repeat child
+    let mut timer = None;
+
+    let message = match command.get_ops() {
         client::Ops::Restart => {
             let name = command.child_name.as_ref().unwrap();
             // check name
@@ -686,22 +1693,20 @@ pub fn day_care(kig: Arc<Mutex<Kindergarten>>, data: String) -> Result<String> {
                 let mut pre_hook_combine = server_conf.call_chain_combine(pre_hook_result.1)?;
                 // reverse call chain
                 pre_hook_combine.reverse();
-                kg.handle_pre_hook(pre_hook_combine)?;
+                kg.handle_pre_hook(&pre_hook_combine)?;
+                record_pre_hook(&mut kg, name, &pre_hook_combine);
             }
 
             match kg.restart(name, &mut conf) {
                 Ok(_) => {
                     // repeat here
-                    let repeat_meg = if conf.is_repeat() {
-                        repeat(conf, Arc::clone(&kig), name.clone())
-                    } else {
-                        String::new()
-                    };
+                    if conf.is_repeat() {
+                        timer = Some(repeat(conf, Arc::clone(&kig), name.clone()));
+                    }
 
                     Ok(format!(
-                        "restart {} success{}",
+                        "restart {} success",
                         command.child_name.as_ref().unwrap(),
-                        repeat_meg,
                     ))
                 }
                 Err(e) => Err(e),
@@ -744,7 +1749,8 @@ pub fn day_care(kig: Arc<Mutex<Kindergarten>>, data: String) -> Result<String> {
                 // reverse call chain
                 if pre_hook_combine.len() != 0 {
                     pre_hook_combine.reverse();
-                    kg.handle_pre_hook(pre_hook_combine)?;
+                    kg.handle_pre_hook(&pre_hook_combine)?;
+                    record_pre_hook(&mut kg, name, &pre_hook_combine);
                     pre_hook_msg.push_str("Find pre-hook, has started pre-hook firstly. ");
                 }
             }
@@ -752,18 +1758,11 @@ pub fn day_care(kig: Arc<Mutex<Kindergarten>>, data: String) -> Result<String> {
             match kg.start(name, &mut conf) {
                 Ok(_) => {
                     // repeat here
-                    let repeat_meg = if conf.is_repeat() {
-                        repeat(conf, Arc::clone(&kig), name.clone())
-                    } else {
-                        String::new()
-                    };
+                    if conf.is_repeat() {
+                        timer = Some(repeat(conf, Arc::clone(&kig), name.clone()));
+                    }
 
-                    Ok(format!(
-                        "{}start {} success{}",
-                        pre_hook_msg,
-                        name.clone(),
-                        repeat_meg
-                    ))
+                    Ok(format!("{}start {} success", pre_hook_msg, name.clone()))
                 }
                 Err(e) => Err(e),
             }
@@ -780,29 +1779,20 @@ pub fn day_care(kig: Arc<Mutex<Kindergarten>>, data: String) -> Result<String> {
             match kg.stop(command.child_name.as_ref().unwrap()) {
                 Ok(_) => {
                     if let Some(post_hook_command) = post_hook {
-                        // connect to supervisor itself
-                        let mut stream = TcpStream::connect((
-                            match server_conf.listener_addr.as_str() {
-                                // listen "::" means connect to ::1
-                                "::" => "::1".to_string(),
-
-                                // listen "0.0.0.0" means connect to 127.0.0.1
-                                "0.0.0.0" => "127.0.0.1".to_string(),
-
-                                // other address
-                                x => x.to_string(),
+                        send_post_hook(&kig, &post_hook_command)?;
+                        kg.record(
+                            command.child_name.as_ref().unwrap(),
+                            Event::HookFired {
+                                hook: "posthook".to_string(),
+                                command: post_hook_command,
                             },
-                            33889,
-                        ))?;
-
-                        stream.write_all(post_hook_command.as_bytes())?;
-                        stream.flush()?;
+                        );
                     }
 
-                    return Ok(format!(
+                    Ok(format!(
                         "stop {} success",
                         command.child_name.as_ref().unwrap()
-                    ));
+                    ))
                 }
                 Err(e) => Err(e),
             }
@@ -831,27 +1821,18 @@ pub fn day_care(kig: Arc<Mutex<Kindergarten>>, data: String) -> Result<String> {
                 match kg.stop(name) {
                     Ok(_) => {
                         if let Some(post_hook_command) = post_hook {
-                            // connect to supervisor itself
-                            let mut stream = TcpStream::connect((
-                                match server_conf.listener_addr.as_str() {
-                                    // listen "::" means connect to ::1
-                                    "::" => "::1".to_string(),
-
-                                    // listen "0.0.0.0" means connect to 127.0.0.1
-                                    "0.0.0.0" => "127.0.0.1".to_string(),
-
-                                    // other address
-                                    x => x.to_string(),
-                                },
-                                33889,
-                            ))?;
-
-                            stream.write_all(post_hook_command.as_bytes())?;
-                            stream.flush()?;
+                            send_post_hook(&kig, &post_hook_command)?;
                             resp.push_str(&format!(
                                 "find post-hook \"{}\", run it after stop. ",
                                 post_hook_command
                             ));
+                            kg.record(
+                                name,
+                                Event::HookFired {
+                                    hook: "posthook".to_string(),
+                                    command: post_hook_command,
+                                },
+                            );
                         }
                         resp.push_str(&format!(
                             "Stop {} success, start it again. ",
@@ -883,22 +1864,20 @@ pub fn day_care(kig: Arc<Mutex<Kindergarten>>, data: String) -> Result<String> {
                 let mut pre_hook_combine = server_conf.call_chain_combine(pre_hook_result.1)?;
                 //reverse call chain
                 pre_hook_combine.reverse();
-                kg.handle_pre_hook(pre_hook_combine)?;
+                kg.handle_pre_hook(&pre_hook_combine)?;
+                record_pre_hook(&mut kg, name, &pre_hook_combine);
             }
 
             match kg.start(name, &mut conf) {
                 Ok(_) => {
                     //repeat here
-                    let repeat_meg = if conf.is_repeat() {
-                        repeat(conf, Arc::clone(&kig), name.clone())
-                    } else {
-                        String::new()
-                    };
+                    if conf.is_repeat() {
+                        timer = Some(repeat(conf, Arc::clone(&kig), name.clone()));
+                    }
 
                     resp.push_str(&format!(
-                        "Start {} success{} ",
+                        "Start {} success",
                         command.child_name.as_ref().unwrap(),
-                        repeat_meg,
                     ));
                     Ok(resp)
                 }
@@ -922,22 +1901,224 @@ pub fn day_care(kig: Arc<Mutex<Kindergarten>>, data: String) -> Result<String> {
         }
 
         client::Ops::Check => {
-            kg.check_status(command.child_name.as_ref().unwrap_or(&String::new()))
+            kg.check_status(command.child_name.as_ref().unwrap_or(&String::new()), scope)
         }
 
-        client::Ops::Info => server_info(server_conf, &kg, command.child_name.as_ref()),
+        client::Ops::Info => server_info(&server_conf, &kg, command.child_name.as_ref(), scope),
 
-        _ => {
+        client::Ops::Logs => {
+            let stream = if command.has_flag(&client::Flags::Stderr) {
+                Stream::Stderr
+            } else {
+                Stream::Stdout
+            };
+            kg.logs(
+                command.child_name.as_ref().unwrap(),
+                stream,
+                command.lines().unwrap_or(DEFAULT_LOGS_LINES),
+            )
+        }
+
+        client::Ops::Reopen => {
+            kg.reopen_logs(command.child_name.as_ref().unwrap_or(&String::new()))
+        }
+
+        client::Ops::Diff => {
+            let name = command.child_name.as_ref().unwrap();
+            kg.diff(name, server_conf.find_config_by_name(name))
+        }
+
+        client::Ops::History => kg.history(command.child_name.as_ref().unwrap()),
+
+        client::Ops::List => list_children(
+            &server_conf,
+            &mut kg,
+            command.child_name.as_deref().unwrap_or(""),
+            scope,
+        ),
+
+        client::Ops::Status => {
+            let mut rows = kg.status_rows(
+                configured_names(&server_conf)?,
+                command.child_name.as_deref().unwrap_or(""),
+            )?;
+            rows.retain(|(name, _)| scope.contains(name));
+            Ok(status_table(&rows))
+        }
+
+        // only for clients have key, it shows what server trusts
+        client::Ops::Keys => {
+            if !kg.encrypt_mode {
+                return Err(ioError::new(
+                    ErrorKind::PermissionDenied,
+                    "keys command needs encrypt mode",
+                ));
+            }
+            if command.child_name.is_some() {
+                return Err(ioError::new(
+                    ErrorKind::InvalidInput,
+                    "keys command has no argument",
+                ));
+            }
+            keys_table(&server_conf, &kg)
+        }
+
+        _ => return Err(ioError::new(ErrorKind::InvalidInput, "not support")),
+    }?;
+
+    let mut resp = Response::new(&command, message);
+    resp.timer = timer;
+
+    // child of command, or all children of ops show them. "all" is not a child
+    resp.children = match command.child_name.as_deref() {
+        Some("all") => vec![],
+        Some(name) => kg.status_rows(vec![name.to_string()], name)?,
+        None if matches!(
+            command.get_ops(),
+            client::Ops::Status | client::Ops::Check | client::Ops::List
+        ) =>
+        {
+            let mut rows = kg.status_rows(configured_names(&server_conf)?, "")?;
+            rows.retain(|(name, _)| scope.contains(name));
+            rows
+        }
+        None => vec![],
+    };
+    Ok(resp)
+}
+
+/// names of all children in load paths
+fn configured_names(config: &ServerConfig) -> Result<Vec<String>> {
+    Ok(config
+        .all_ymls_in_load_path()?
+        .into_iter()
+        .map(|(name, _)| name)
+        .collect())
+}
+
+/// handler command "list", all children in load paths, running or not.
+/// empty name means all
+fn list_children(
+    config: &ServerConfig,
+    kg: &mut Kindergarten,
+    name: &str,
+    scope: &ChildScope,
+) -> Result<String> {
+    let mut children = config.all_ymls_in_load_path()?;
+    children.retain(|(n, _)| scope.contains(n));
+    children.sort();
+    if !name.is_empty() {
+        children.retain(|(n, _)| n == name);
+        if children.is_empty() {
             return Err(ioError::new(
-                ErrorKind::InvalidInput,
-                logger::timelog("not support"),
-            ))
+                ErrorKind::NotFound,
+                format!("Cannot found '{}' file in load path", name),
+            ));
         }
     }
+
+    let yes_no = |b: bool| if b { "yes" } else { "no" }.to_string();
+    let mut cells = vec![["NAME", "RUNNING", "REPEAT", "HOOKS", "CHANGED", "CONFIG"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect::<Vec<String>>()];
+    for (name, path) in children {
+        let running = kg.get_child_config(&name);
+        let on_disk = Config::read_from_yaml_file((&path).into());
+
+        // config on disk is what list shows, running one if disk is broken
+        let conf = on_disk.as_ref().ok().or(running.as_ref());
+        let changed = match &running {
+            None => "-".to_string(),
+            Some(r) => r
+                .config_drifted()
+                .map_or("file missing".to_string(), yes_no),
+        };
+
+        cells.push(vec![
+            name,
+            yes_no(running.is_some()),
+            conf.map_or("-".to_string(), |c| yes_no(c.is_repeat())),
+            conf.map_or("-".to_string(), |c| yes_no(c.has_hook())),
+            changed,
+            path,
+        ]);
+    }
+
+    Ok(format_table(&cells))
+}
+
+/// handler command "keys", keys in keys path not revoked, with time of last command
+fn keys_table(config: &ServerConfig, kg: &Kindergarten) -> Result<String> {
+    let mut keys = config.trusted_keys()?;
+    keys.sort_by(|a, b| (&a.name, &a.path).cmp(&(&b.name, &b.path)));
+
+    let mut cells = vec![["NAME", "FINGERPRINT", "LAST USED", "PATH"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect::<Vec<String>>()];
+    for k in keys {
+        let fp = fingerprint(&k.key)?;
+        if config.is_revoked(&k.name, Some(&fp)) {
+            continue;
+        }
+        let last_used = kg.keys_last_used.get(&k.name).map_or("-".to_string(), |t| {
+            t.format("%Y-%m-%d %H:%M:%S").to_string()
+        });
+        cells.push(vec![k.name, fp, last_used, k.path]);
+    }
+
+    Ok(format_table(&cells))
+}
+
+/// record prehook chain in history of child, chain is Vec<(command, name, config)>
+fn record_pre_hook(kg: &mut Kindergarten, name: &str, chain: &[(String, String, Config)]) {
+    if chain.is_empty() {
+        return;
+    }
+
+    let command = chain
+        .iter()
+        .map(|(comm, child, _)| format!("{} {}", comm, child))
+        .collect::<Vec<String>>()
+        .join(", ");
+    kg.record(
+        name,
+        Event::HookFired {
+            hook: "prehook".to_string(),
+            command,
+        },
+    );
+}
+
+/// run posthook command inside server, like a command from client with every permission.
+/// it runs after current command releases kindergarten, and goes to audit log
+fn send_post_hook(kig: &Arc<Mutex<Kindergarten>>, command: &str) -> Result<()> {
+    let kig = Arc::clone(kig);
+    let mut record = audit::Record::new("posthook");
+    record.transport = "internal";
+    record.command = Some(command.to_string());
+    thread::spawn(move || {
+        let command = record.command.clone().unwrap_or_default();
+        let result = day_care(kig, command.clone(), "posthook", &ChildScope::all())
+            .map(|resp| resp.to_string());
+        match &result {
+            Ok(resp) => logger::debug(
+                "posthook sent",
+                &[("command", &command), ("response", resp.trim())],
+            ),
+            Err(e) => logger::error(
+                "posthook failed",
+                &[("command", &command), ("error", &e.to_string())],
+            ),
+        }
+        write_audit(&record, &result);
+    });
+    Ok(())
 }
 
 /// receive child config, KG, and filename of child config, repeat function
-fn repeat(conf: Config, kig: Arc<Mutex<Kindergarten>>, name: String) -> String {
+fn repeat(conf: Config, kig: Arc<Mutex<Kindergarten>>, name: String) -> TimerInfo {
     // clone locked val to timer
     let timer_lock_val = Arc::clone(&kig);
     let next_time = conf.to_duration().unwrap();
@@ -949,11 +2130,16 @@ fn repeat(conf: Config, kig: Arc<Mutex<Kindergarten>>, name: String) -> String {
             .unwrap()
             .run(timer_lock_val)
     });
-    format!(", and it will {} in {:?}", comm, next_time)
+    TimerInfo::new(&comm, next_time)
 }
 
 /// Server apply info of itself to client
-fn server_info(config: ServerConfig, kg: &Kindergarten, name: Option<&String>) -> Result<String> {
+fn server_info(
+    config: &ServerConfig,
+    kg: &Kindergarten,
+    name: Option<&String>,
+    scope: &ChildScope,
+) -> Result<String> {
     let name = name.map_or("all", |n| n.as_str());
 
     let mut resp = String::from("==Server Info Below==\n");
@@ -967,10 +2153,13 @@ fn server_info(config: ServerConfig, kg: &Kindergarten, name: Option<&String>) -
             // children name are running
             resp.push_str("Children are running (use 'check' command to see child detail):\n");
             let mut s = String::from("[");
-            kg.all_running_children().iter().for_each(|name| {
-                s.push_str(name);
-                s.push_str(" ");
-            });
+            kg.all_running_children()
+                .iter()
+                .filter(|name| scope.contains(name))
+                .for_each(|name| {
+                    s.push_str(name);
+                    s.push_str(" ");
+                });
             resp.push_str(&s);
             resp.push_str("]");
             resp.push_str("\n");
@@ -987,3 +2176,50 @@ fn server_info(config: ServerConfig, kg: &Kindergarten, name: Option<&String>) -
     resp.push_str("=======================\n");
     Ok(resp)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hmac_key_name() {
+        let config =
+            ServerConfig::read_from_str("encrypt: hmac\nhmac_secret: /etc/hmac.secret").unwrap();
+        assert!(config.check_hmac_key(HMAC_KEY_NAME).is_ok());
+
+        // signed by secret, but claims to be another key
+        let e = config.check_hmac_key("alice").unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::PermissionDenied);
+
+        let config = ServerConfig::read_from_str(
+            "encrypt: hmac\nhmac_secret: /etc/hmac.secret\nrevoked_keys: [hmac]",
+        )
+        .unwrap();
+        assert!(config.check_hmac_key(HMAC_KEY_NAME).is_err());
+    }
+
+    #[test]
+    fn test_port_fields() {
+        let config = ServerConfig::read_from_str("listener_port: 33890\nhttp_port: 8080").unwrap();
+        assert_eq!(config.listener_port, 33890);
+        assert_eq!(config.http_port, Some(8080));
+
+        let config = ServerConfig::read_from_str("mode: quiet").unwrap();
+        assert_eq!(config.listener_port, client::DEFAULT_PORT);
+        assert_eq!(config.http_port, None);
+
+        for bad in [
+            "listener_port: \"33890\"",
+            "listener_port: 0",
+            "listener_port: 65536",
+            "http_port: -1",
+        ] {
+            assert_eq!(
+                ServerConfig::read_from_str(bad).unwrap_err().kind(),
+                ErrorKind::InvalidInput,
+                "{}",
+                bad
+            );
+        }
+    }
+}
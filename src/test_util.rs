@@ -0,0 +1,18 @@
+//! fixtures shared by tests of all modules
+use std::path::PathBuf;
+use std::{env, fs, process};
+
+/// path in temp dir, unique for name and test process, anything left there is removed
+pub fn temp_path(name: &str) -> PathBuf {
+    let path = env::temp_dir().join(format!("supervisor-rs-{}-{}", name, process::id()));
+    let _ = fs::remove_dir_all(&path);
+    let _ = fs::remove_file(&path);
+    path
+}
+
+/// empty dir in temp dir, unique for name and test process
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = temp_path(name);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
@@ -1,3 +1,4 @@
+use super::acl::ChildScope;
 use super::kindergarten::*;
 use super::server;
 use super::*;
@@ -47,31 +48,39 @@ impl Timer {
 
         //check if this timer still works
         if !self.check(kig.clone()) {
-            println!(
-                "{}",
-                logger::timelog(&format!(
-                    "check failed when timer try to run \"{} {}\"",
-                    self.comm.clone(),
-                    self.name.clone()
-                ))
+            logger::debug(
+                "timer is outdated, skip",
+                &[
+                    ("child", &self.name),
+                    ("pid", &self.id.to_string()),
+                    ("op", &self.comm),
+                ],
             );
             return;
         }
 
         // call server::day_care to run the repeat command
-        match server::day_care(kig, format!("{} {}", self.comm.clone(), self.name.clone())) {
-            Err(e) => println!(
-                "{}",
-                logger::timelog(&format!("Timer is up, but {:?}", e.to_string()))
+        match server::day_care(
+            kig,
+            format!("{} {}", self.comm.clone(), self.name.clone()),
+            "timer",
+            &ChildScope::all(),
+        ) {
+            Err(e) => logger::error(
+                "Timer is up, but command failed",
+                &[
+                    ("child", &self.name),
+                    ("op", &self.comm),
+                    ("error", &e.to_string()),
+                ],
             ),
-            Ok(m) => println!(
-                "{}\n{}",
-                logger::timelog(&format!(
-                    "Timer is up, run \"{} {}\"",
-                    self.comm.clone(),
-                    self.name.clone(),
-                )),
-                logger::timelog(&m),
+            Ok(m) => logger::info(
+                "Timer is up",
+                &[
+                    ("child", &self.name),
+                    ("op", &self.comm),
+                    ("response", m.to_string().trim()),
+                ],
             ),
         }
     }
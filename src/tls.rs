@@ -0,0 +1,151 @@
+//! TLS of TCP listener and client connection, with optional client certificate
+use openssl::nid::Nid;
+use openssl::ssl::{
+    SslAcceptor, SslConnector, SslFiletype, SslMethod, SslRef, SslStream, SslVerifyMode,
+};
+use openssl::x509::X509Name;
+use std::io::{Error as ioError, ErrorKind, Result};
+use std::net::TcpStream;
+use yaml_rust::Yaml;
+
+/// tls field of server config
+///
+/// ```yaml
+/// tls:
+///   cert: /etc/supervisor-rs/server.crt
+///   key: /etc/supervisor-rs/server.key
+///   # clients have to give certificate signed by it
+///   client_ca: /etc/supervisor-rs/ca.crt
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct TlsConfig {
+    /// server certificate chain, PEM
+    pub cert: String,
+
+    /// private key of cert, PEM
+    pub key: String,
+
+    /// CA of client certificates, mutual authentication is off if not given
+    pub client_ca: Option<String>,
+}
+
+impl TlsConfig {
+    pub fn new(input: &Yaml) -> Result<Self> {
+        let field = |name: &str| {
+            input[name].as_str().map(|s| s.to_string()).ok_or_else(|| {
+                ioError::new(ErrorKind::InvalidData, format!("tls needs {} field", name))
+            })
+        };
+
+        Ok(TlsConfig {
+            cert: field("cert")?,
+            key: field("key")?,
+            client_ca: input["client_ca"].as_str().map(|s| s.to_string()),
+        })
+    }
+
+    pub fn acceptor(&self) -> Result<SslAcceptor> {
+        let mut builder = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls_server())?;
+        builder.set_certificate_chain_file(&self.cert)?;
+        builder.set_private_key_file(&self.key, SslFiletype::PEM)?;
+        builder.check_private_key()?;
+
+        if let Some(ca) = &self.client_ca {
+            builder.set_ca_file(ca)?;
+            builder.set_client_ca_list(X509Name::load_client_ca_file(ca)?);
+            builder.set_verify(SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT);
+        }
+
+        Ok(builder.build())
+    }
+
+    /// TLS handshake with client just accepted
+    pub fn accept(acceptor: &SslAcceptor, stream: TcpStream) -> Result<SslStream<TcpStream>> {
+        acceptor.accept(stream).map_err(|e| {
+            ioError::new(
+                ErrorKind::PermissionDenied,
+                format!("tls handshake failed: {}", e),
+            )
+        })
+    }
+}
+
+/// TLS options of client, from --tls, --ca, --cert and --key flags
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct TlsOptions {
+    /// CA verifies server certificate, system roots if not given
+    pub ca: Option<String>,
+
+    /// client certificate, for server has client_ca
+    pub cert: Option<String>,
+
+    /// private key of client certificate, default is cert file itself
+    pub key: Option<String>,
+}
+
+impl TlsOptions {
+    pub fn connector(&self) -> Result<SslConnector> {
+        let mut builder = SslConnector::builder(SslMethod::tls_client())?;
+        if let Some(ca) = &self.ca {
+            builder.set_ca_file(ca)?;
+        }
+
+        if let Some(cert) = &self.cert {
+            builder.set_certificate_chain_file(cert)?;
+            builder.set_private_key_file(self.key.as_ref().unwrap_or(cert), SslFiletype::PEM)?;
+            builder.check_private_key()?;
+        } else if self.key.is_some() {
+            return Err(ioError::new(ErrorKind::InvalidInput, "--key needs --cert"));
+        }
+
+        Ok(builder.build())
+    }
+
+    /// TLS handshake with server, domain is hostname or ip address in certificate
+    pub fn connect(&self, domain: &str, stream: TcpStream) -> Result<SslStream<TcpStream>> {
+        self.connector()?.connect(domain, stream).map_err(|e| {
+            ioError::new(
+                ErrorKind::PermissionDenied,
+                format!("tls handshake with {} failed: {}", domain, e),
+            )
+        })
+    }
+}
+
+/// common name of verified peer certificate
+pub fn peer_name(ssl: &SslRef) -> Option<String> {
+    let cert = ssl.peer_certificate()?;
+    let entry = cert.subject_name().entries_by_nid(Nid::COMMONNAME).next()?;
+    entry.data().to_string().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use yaml_rust::YamlLoader;
+
+    #[test]
+    fn test_tls_config() {
+        let docs = YamlLoader::load_from_str(
+            "a:\n  cert: /tmp/server.crt\n  key: /tmp/server.key\n  client_ca: /tmp/ca.crt\nb:\n  cert: /tmp/server.crt",
+        )
+        .unwrap();
+
+        assert_eq!(
+            TlsConfig::new(&docs[0]["a"]).unwrap(),
+            TlsConfig {
+                cert: "/tmp/server.crt".to_string(),
+                key: "/tmp/server.key".to_string(),
+                client_ca: Some("/tmp/ca.crt".to_string()),
+            }
+        );
+        assert!(TlsConfig::new(&docs[0]["b"]).is_err());
+
+        // key without cert
+        let opts = TlsOptions {
+            key: Some("/tmp/client.key".to_string()),
+            ..Default::default()
+        };
+        assert!(opts.connector().is_err());
+    }
+}
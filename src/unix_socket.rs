@@ -0,0 +1,209 @@
+//! unix domain socket listener of server, for local clients
+use std::fs;
+use std::io::{Error as ioError, ErrorKind, Result};
+use std::os::unix::fs::{chown, FileTypeExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use yaml_rust::Yaml;
+
+/// unix_socket field of server config
+///
+/// ```yaml
+/// unix_socket: /run/supervisor-rs.sock
+/// # or
+/// unix_socket:
+///   path: /run/supervisor-rs.sock
+///   mode: "0660"
+///   owner: root
+///   group: wheel
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnixSocketConfig {
+    pub path: String,
+
+    /// permission bits of socket file, like 0o660
+    pub mode: Option<u32>,
+
+    /// user name or uid
+    pub owner: Option<String>,
+
+    /// group name or gid
+    pub group: Option<String>,
+}
+
+impl UnixSocketConfig {
+    pub fn new(input: &Yaml) -> Result<Self> {
+        let mut conf = UnixSocketConfig {
+            path: String::new(),
+            mode: None,
+            owner: None,
+            group: None,
+        };
+
+        match input {
+            Yaml::String(path) => conf.path = path.clone(),
+            Yaml::Hash(_) => {
+                conf.path = input["path"]
+                    .as_str()
+                    .ok_or_else(|| ioError::new(ErrorKind::InvalidData, "unix_socket no path"))?
+                    .to_string();
+
+                conf.mode = match &input["mode"] {
+                    Yaml::BadValue => None,
+                    Yaml::String(m) => Some(parse_mode(m)?),
+                    // yaml reads 0660 as decimal 660
+                    Yaml::Integer(m) => Some(parse_mode(&m.to_string())?),
+                    m => {
+                        return Err(ioError::new(
+                            ErrorKind::InvalidData,
+                            format!("unix_socket mode {:?} is illegal", m),
+                        ))
+                    }
+                };
+
+                conf.owner = id_field(&input["owner"]);
+                conf.group = id_field(&input["group"]);
+            }
+            _ => {
+                return Err(ioError::new(
+                    ErrorKind::InvalidData,
+                    "unix_socket should be path or hash",
+                ))
+            }
+        }
+
+        Ok(conf)
+    }
+
+    /// bind socket file and set its mode and owner.
+    /// socket file left by dead server is removed first
+    pub fn bind(&self) -> Result<UnixListener> {
+        let path = Path::new(&self.path);
+        if let Ok(meta) = fs::symlink_metadata(path) {
+            if !meta.file_type().is_socket() {
+                return Err(ioError::new(
+                    ErrorKind::AlreadyExists,
+                    format!("{} exists and is not a socket", self.path),
+                ));
+            }
+            if UnixStream::connect(path).is_ok() {
+                return Err(ioError::new(
+                    ErrorKind::AddrInUse,
+                    format!("{} is used by another server", self.path),
+                ));
+            }
+            fs::remove_file(path)?;
+        }
+
+        let listener = UnixListener::bind(path)?;
+
+        if let Some(mode) = self.mode {
+            fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+        }
+
+        let uid = match &self.owner {
+            Some(o) => Some(lookup_id("/etc/passwd", o)?),
+            None => None,
+        };
+        let gid = match &self.group {
+            Some(g) => Some(lookup_id("/etc/group", g)?),
+            None => None,
+        };
+        if uid.is_some() || gid.is_some() {
+            chown(path, uid, gid)?;
+        }
+
+        Ok(listener)
+    }
+}
+
+/// mode like "0660" or "660", octal
+fn parse_mode(s: &str) -> Result<u32> {
+    match u32::from_str_radix(s.trim_start_matches("0o"), 8) {
+        Ok(m) if m <= 0o777 => Ok(m),
+        _ => Err(ioError::new(
+            ErrorKind::InvalidData,
+            format!("unix_socket mode {} is illegal", s),
+        )),
+    }
+}
+
+fn id_field(input: &Yaml) -> Option<String> {
+    match input {
+        Yaml::String(s) => Some(s.clone()),
+        Yaml::Integer(n) => Some(n.to_string()),
+        _ => None,
+    }
+}
+
+/// numeric id, or id of name in /etc/passwd or /etc/group
+fn lookup_id(file: &str, name: &str) -> Result<u32> {
+    if let Ok(id) = name.parse::<u32>() {
+        return Ok(id);
+    }
+
+    fs::read_to_string(file)?
+        .lines()
+        .map(|line| line.split(':').collect::<Vec<&str>>())
+        .find(|fields| fields.len() > 2 && fields[0] == name)
+        .and_then(|fields| fields[2].parse::<u32>().ok())
+        .ok_or_else(|| {
+            ioError::new(
+                ErrorKind::NotFound,
+                format!("cannot find {} in {}", name, file),
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_util::temp_path;
+    use super::*;
+    use yaml_rust::YamlLoader;
+
+    #[test]
+    fn test_unix_socket_config() {
+        let docs = YamlLoader::load_from_str(
+            "a: /tmp/a.sock\nb:\n  path: /tmp/b.sock\n  mode: \"0660\"\n  owner: root\n  group: 0\nc:\n  path: /tmp/c.sock\n  mode: 999",
+        )
+        .unwrap();
+
+        assert_eq!(
+            UnixSocketConfig::new(&docs[0]["a"]).unwrap().path,
+            "/tmp/a.sock"
+        );
+
+        let b = UnixSocketConfig::new(&docs[0]["b"]).unwrap();
+        assert_eq!(b.mode, Some(0o660));
+        assert_eq!(b.owner, Some("root".to_string()));
+        assert_eq!(b.group, Some("0".to_string()));
+        assert_eq!(lookup_id("/etc/passwd", "root").unwrap(), 0);
+        assert!(lookup_id("/etc/passwd", "no-such-user-here").is_err());
+
+        assert!(UnixSocketConfig::new(&docs[0]["c"]).is_err());
+    }
+
+    #[test]
+    fn test_bind_replaces_stale_socket() {
+        let path = temp_path("sock");
+        let conf = UnixSocketConfig {
+            path: path.to_str().unwrap().to_string(),
+            mode: Some(0o600),
+            owner: None,
+            group: None,
+        };
+
+        let listener = conf.bind().unwrap();
+        assert_eq!(
+            fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+            0o600
+        );
+        // still in use
+        assert!(conf.bind().is_err());
+
+        // stale after server is gone
+        drop(listener);
+        assert!(conf.bind().is_ok());
+        fs::remove_file(&path).unwrap();
+    }
+}